@@ -9,9 +9,45 @@ pub struct FileArg {
     pub is_custom: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// crawl the configured tags
+    Run,
+    /// re-run only the posts recorded in the failed ledger
+    RetryFailed,
+    /// search the archive, the query is in `Args::query`
+    Search,
+    /// list groups of near duplicate images
+    Dupes,
+    /// record files saved by other tools, the directories are in `Args::paths`
+    Import,
+    /// check the saved files against the database
+    Verify,
+    /// list or remove files without posts and posts without files
+    Gc,
+}
+
 pub struct Args {
     pub config: FileArg,
     pub database: FileArg,
+    pub command: Command,
+    /// search terms, e.g. `tag1 -tag2 rating:s score:>50`
+    pub query: Vec<String>,
+    /// print results as json
+    pub json: bool,
+    /// hamming distance for `dupes`, `near_distance` of the config when not set
+    pub distance: Option<u32>,
+    /// directories to import
+    pub paths: Vec<PathBuf>,
+    /// move imported files to the configured `target_dir` and `filename`
+    pub move_files: bool,
+    /// import without asking the platforms, only files named after their post are imported
+    /// and they stay in place
+    pub offline: bool,
+    /// record the posts `verify` or `gc` found broken as failed
+    pub requeue: bool,
+    /// let `gc` remove what it found
+    pub apply: bool,
 }
 
 impl Default for Args {
@@ -27,6 +63,15 @@ impl Default for Args {
                     .collect::<PathBuf>(),
                 is_custom: false,
             },
+            command: Command::Run,
+            query: Vec::new(),
+            json: false,
+            distance: None,
+            paths: Vec::new(),
+            move_files: false,
+            offline: false,
+            requeue: false,
+            apply: false,
         }
     }
 }
@@ -91,7 +136,80 @@ impl Args {
                     }
                     None => panic!("arg '{}' used but no path specified.", arg.as_str()),
                 },
-                "--" => break,
+                "retry-failed" => {
+                    args.command = Command::RetryFailed;
+                    i += 1;
+                }
+                "search" => {
+                    args.command = Command::Search;
+                    i += 1;
+                }
+                "dupes" => {
+                    args.command = Command::Dupes;
+                    i += 1;
+                }
+                "--distance" => match _args_.get(i + 1).map(|d| d.parse::<u32>()) {
+                    Some(Ok(distance)) => {
+                        args.distance = Some(distance);
+                        iter.next();
+                        i += 2;
+                    }
+                    Some(Err(e)) => {
+                        eprintln!("invalid distance: {e}");
+                        exit(1);
+                    }
+                    None => {
+                        eprintln!("arg '{}' is used but no distance specified.", arg.as_str());
+                        exit(1);
+                    }
+                },
+                "import" => {
+                    args.command = Command::Import;
+                    i += 1;
+                }
+                "verify" => {
+                    args.command = Command::Verify;
+                    i += 1;
+                }
+                "gc" => {
+                    args.command = Command::Gc;
+                    i += 1;
+                }
+                "--apply" => {
+                    args.apply = true;
+                    i += 1;
+                }
+                "--requeue" => {
+                    args.requeue = true;
+                    i += 1;
+                }
+                "--move" => {
+                    args.move_files = true;
+                    i += 1;
+                }
+                "--offline" => {
+                    args.offline = true;
+                    i += 1;
+                }
+                "--json" => {
+                    args.json = true;
+                    i += 1;
+                }
+                "--" => {
+                    // lets negated tags that look like flags through, e.g. `search -- -c`
+                    if args.command == Command::Search {
+                        args.query.extend(iter.by_ref());
+                    }
+                    break;
+                }
+                _ if args.command == Command::Search => {
+                    args.query.push(arg);
+                    i += 1;
+                }
+                _ if args.command == Command::Import => {
+                    args.paths.push(PathBuf::from(arg));
+                    i += 1;
+                }
                 _ => panic!("unexpected argument: {}", arg),
             }
         }
@@ -0,0 +1,132 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+// crate
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, COOKIE},
+    Certificate, Client, RequestBuilder, Response, Url,
+};
+
+// local
+use crate::{config::Proxy, cookies::CookieJar, Error, PlatformConfig};
+
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// builds the http client of a platform from its config,
+/// the total request timeout is set per api request from `timeout`,
+/// file transfers are only bounded by `read_timeout`.
+pub fn build(config: &PlatformConfig) -> Result<Client, Error> {
+    let mut builder = Client::builder()
+        .user_agent(config.user_agent.as_str())
+        .connect_timeout(Duration::from_secs_f32(config.connect_timeout))
+        .read_timeout(Duration::from_secs_f32(config.read_timeout));
+    // without a proxy the connection is direct, `HTTP_PROXY` and the like aren't read either
+    match config.proxy {
+        Some(ref proxy) => builder = builder.proxy(build_proxy(proxy)?),
+        None => builder = builder.no_proxy(),
+    }
+    match config.http_version.as_deref() {
+        None | Some("auto") => (),
+        Some("1.1") | Some("1") => builder = builder.http1_only(),
+        Some("2") => builder = builder.http2_prior_knowledge(),
+        Some(v) => {
+            return Err(Error::Client(format!(
+                "unsupported http_version: {v}, expected one of: auto, 1.1, 2"
+            )))
+        }
+    }
+    if let Some(ref path) = config.ca_bundle {
+        let certs = match fs::read(path)
+            .map_err(|e| e.to_string())
+            .and_then(|pem| Certificate::from_pem_bundle(&pem).map_err(|e| e.to_string()))
+        {
+            Ok(c) => c,
+            Err(e) => {
+                return Err(Error::Client(format!(
+                    "unable to load ca bundle: {path}: {e}"
+                )))
+            }
+        };
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    match builder.build() {
+        Ok(c) => return Ok(c),
+        Err(e) => return Err(Error::Client(format!("unable to build http client: {e}"))),
+    }
+}
+
+fn build_proxy(proxy: &Proxy) -> Result<reqwest::Proxy, Error> {
+    match proxy.url.split_once("://") {
+        Some((scheme, _)) if PROXY_SCHEMES.contains(&scheme) => (),
+        _ => {
+            return Err(Error::Client(format!(
+                "unsupported proxy: {}, expected one of: {}",
+                proxy.url,
+                PROXY_SCHEMES.join(", ")
+            )))
+        }
+    }
+    let mut ret = match reqwest::Proxy::all(proxy.url.as_str()) {
+        Ok(p) => p,
+        Err(e) => {
+            return Err(Error::Client(format!(
+                "invalid proxy url: {}: {e}",
+                proxy.url
+            )))
+        }
+    };
+    if let Some(username) = proxy.username.as_ref() {
+        ret = ret.basic_auth(username, proxy.password.as_deref().unwrap_or_default());
+    }
+    return Ok(ret);
+}
+
+/// client of a platform along with its custom headers and cookies,
+/// used for both api calls and downloads.
+pub struct Session {
+    pub client: Client,
+    pub headers: HeaderMap,
+    pub cookies: CookieJar,
+}
+
+impl Session {
+    pub fn new(config: &PlatformConfig, root: &str) -> Result<Self, Error> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in config.headers.iter() {
+            match (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_str(value.as_str()),
+            ) {
+                (Ok(n), Ok(v)) => {
+                    headers.insert(n, v);
+                }
+                _ => return Err(Error::Client(format!("invalid header: {name}: {value}"))),
+            }
+        }
+        return Ok(Self {
+            client: build(config)?,
+            headers,
+            cookies: CookieJar::new(
+                config.cookie_file.as_ref().map(PathBuf::from),
+                &config.cookies,
+                root,
+            ),
+        });
+    }
+
+    pub fn get(&self, url: &str) -> RequestBuilder {
+        let mut request = self.client.get(url).headers(self.headers.clone());
+        if let Some(cookie) = Url::parse(url)
+            .ok()
+            .and_then(|url| self.cookies.header(&url))
+        {
+            request = request.header(COOKIE, cookie);
+        }
+        return request;
+    }
+
+    pub fn store_cookies(&self, response: &Response) {
+        self.cookies.store(response.url(), response.headers());
+    }
+}
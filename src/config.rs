@@ -1,6 +1,6 @@
-use std::{fmt, process::exit};
+use std::{collections::BTreeMap, fmt, path::PathBuf, process::exit};
 
-use crate::{platforms::base::Platform, pub_struct, statics::HOME};
+use crate::{platforms::base::Platform, pub_struct, schema, statics::HOME, utils};
 use serde::{
     de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor},
     Deserialize,
@@ -26,6 +26,77 @@ impl Default for Compress {
     }
 }
 
+/// proxy url with an optional login, supported schemes are
+/// `http`, `https`, `socks5` and `socks5h`.
+/// platforms can set `proxy` to `null` to connect directly while a global proxy is set.
+/// without a proxy the `HTTP_PROXY`, `HTTPS_PROXY` and `ALL_PROXY` env vars are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+pub struct Proxy {
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// what to do when a download doesn't fit in the free space minus `reserve`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowSpace {
+    /// wait until there's enough space
+    Pause,
+    /// skip the post, it's recorded as failed
+    Skip,
+}
+
+/// what to do with a post whose file was already saved by another platform, found by md5
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CrossDuplicate {
+    /// don't save the file again, the post points to the existing file
+    Skip,
+    /// hardlink the existing file to the path of the post
+    Hardlink,
+    /// symlink the existing file to the path of the post
+    Symlink,
+    /// download the file anyway
+    Download,
+}
+
+impl CrossDuplicate {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrossDuplicate::Skip => "skip",
+            CrossDuplicate::Hardlink => "hardlink",
+            CrossDuplicate::Symlink => "symlink",
+            CrossDuplicate::Download => "download",
+        }
+    }
+}
+
+/// size in bytes, either a number or a string like `"20 GB"`
+#[derive(Debug, Clone, Copy)]
+pub struct ByteSize(pub u64);
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Size {
+            Bytes(u64),
+            Text(String),
+        }
+        match Size::deserialize(deserializer)? {
+            Size::Bytes(n) => Ok(ByteSize(n)),
+            Size::Text(s) => match utils::parse_size(s.as_str()) {
+                Some(n) => Ok(ByteSize(n)),
+                None => Err(de::Error::custom(format!("invalid size: {s}"))),
+            },
+        }
+    }
+}
+
 //#[derive(Debug)]
 #[allow(dead_code)]
 pub struct Config {
@@ -64,6 +135,37 @@ impl Config {
         }
         return conf;
     }
+
+    /// the database of a platform, `--database` overrides the config.
+    /// platforms that aren't configured use the global database.
+    pub fn database(&self, platform: &str) -> PathBuf {
+        use crate::statics::ARGS;
+
+        if ARGS.database.is_custom {
+            return ARGS.database.path.clone();
+        }
+        match self.platforms.iter().find(|p| p.name() == platform) {
+            Some(p) => return PathBuf::from(p.config().database.as_str()),
+            None => return PathBuf::from(self.global.database.as_str()),
+        }
+    }
+
+    /// every database with the platform tables read from it, each table is read from
+    /// the database of its platform. databases that don't exist yet are left out.
+    pub fn databases(&self) -> Vec<(PathBuf, Vec<&'static str>)> {
+        let mut ret: Vec<(PathBuf, Vec<&'static str>)> = Vec::new();
+        for table in schema::platform_tables() {
+            let database = self.database(table);
+            if !database.exists() {
+                continue;
+            }
+            match ret.iter_mut().find(|(path, _)| *path == database) {
+                Some((_, tables)) => tables.push(table),
+                None => ret.push((database, vec![table])),
+            }
+        }
+        return ret;
+    }
 }
 
 impl<'de> Deserialize<'de> for Config {
@@ -91,6 +193,24 @@ impl<'de> Deserialize<'de> for Config {
             DirnameRepl,
             Tags,
             Blacklist,
+            MaxRetrySleep,
+            Proxy,
+            Headers,
+            UserAgent,
+            ConnectTimeout,
+            ReadTimeout,
+            HttpVersion,
+            CaBundle,
+            Reserve,
+            LowSpace,
+            Budget,
+            Segments,
+            SegmentThreshold,
+            DownloadWorkers,
+            PrefetchPages,
+            CrossDuplicates,
+            NearDistance,
+            SkipNearDuplicates,
             // platforms
             Yandere,
             Sakugabooru,
@@ -127,6 +247,24 @@ impl<'de> Deserialize<'de> for Config {
                 let mut dirname_repl: Option<()> = None;
                 let mut tags: Option<()> = None;
                 let mut blacklist: Option<()> = None;
+                let mut max_retry_sleep: Option<()> = None;
+                let mut proxy: Option<()> = None;
+                let mut headers: Option<()> = None;
+                let mut user_agent: Option<()> = None;
+                let mut connect_timeout: Option<()> = None;
+                let mut read_timeout: Option<()> = None;
+                let mut http_version: Option<()> = None;
+                let mut ca_bundle: Option<()> = None;
+                let mut reserve: Option<()> = None;
+                let mut low_space: Option<()> = None;
+                let mut budget: Option<()> = None;
+                let mut segments: Option<()> = None;
+                let mut segment_threshold: Option<()> = None;
+                let mut download_workers: Option<()> = None;
+                let mut prefetch_pages: Option<()> = None;
+                let mut cross_duplicates: Option<()> = None;
+                let mut near_distance: Option<()> = None;
+                let mut skip_near_duplicates: Option<()> = None;
                 let mut platforms: Vec<Platform> = Vec::new();
                 let mut yandere = false;
                 let mut sakugabooru = false;
@@ -281,6 +419,150 @@ impl<'de> Deserialize<'de> for Config {
                             blacklist = Some(());
                             global_config.blacklist = val;
                         }
+                        Field::MaxRetrySleep => {
+                            if max_retry_sleep.is_some() {
+                                return Err(de::Error::duplicate_field("max_retry_sleep"));
+                            }
+                            let val = map.next_value()?;
+                            max_retry_sleep = Some(());
+                            global_config.max_retry_sleep = val;
+                        }
+                        Field::Proxy => {
+                            if proxy.is_some() {
+                                return Err(de::Error::duplicate_field("proxy"));
+                            }
+                            let val: Option<Proxy> = map.next_value()?;
+                            proxy = Some(());
+                            global_config.proxy = val;
+                        }
+                        Field::Headers => {
+                            if headers.is_some() {
+                                return Err(de::Error::duplicate_field("headers"));
+                            }
+                            let val = map.next_value()?;
+                            headers = Some(());
+                            global_config.headers = val;
+                        }
+                        Field::UserAgent => {
+                            if user_agent.is_some() {
+                                return Err(de::Error::duplicate_field("user_agent"));
+                            }
+                            let val = map.next_value()?;
+                            user_agent = Some(());
+                            global_config.user_agent = val;
+                        }
+                        Field::ConnectTimeout => {
+                            if connect_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("connect_timeout"));
+                            }
+                            let val = map.next_value()?;
+                            connect_timeout = Some(());
+                            global_config.connect_timeout = val;
+                        }
+                        Field::ReadTimeout => {
+                            if read_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("read_timeout"));
+                            }
+                            let val = map.next_value()?;
+                            read_timeout = Some(());
+                            global_config.read_timeout = val;
+                        }
+                        Field::HttpVersion => {
+                            if http_version.is_some() {
+                                return Err(de::Error::duplicate_field("http_version"));
+                            }
+                            let val = map.next_value()?;
+                            http_version = Some(());
+                            global_config.http_version = val;
+                        }
+                        Field::CaBundle => {
+                            if ca_bundle.is_some() {
+                                return Err(de::Error::duplicate_field("ca_bundle"));
+                            }
+                            let val: String = map.next_value()?;
+                            ca_bundle = Some(());
+                            global_config.ca_bundle = Some(expand_home(val));
+                        }
+                        Field::Reserve => {
+                            if reserve.is_some() {
+                                return Err(de::Error::duplicate_field("reserve"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            reserve = Some(());
+                            global_config.reserve = val.0;
+                        }
+                        Field::LowSpace => {
+                            if low_space.is_some() {
+                                return Err(de::Error::duplicate_field("low_space"));
+                            }
+                            let val = map.next_value()?;
+                            low_space = Some(());
+                            global_config.low_space = val;
+                        }
+                        Field::Budget => {
+                            if budget.is_some() {
+                                return Err(de::Error::duplicate_field("budget"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            budget = Some(());
+                            global_config.budget = Some(val.0);
+                        }
+                        Field::Segments => {
+                            if segments.is_some() {
+                                return Err(de::Error::duplicate_field("segments"));
+                            }
+                            let val = map.next_value()?;
+                            segments = Some(());
+                            global_config.segments = val;
+                        }
+                        Field::SegmentThreshold => {
+                            if segment_threshold.is_some() {
+                                return Err(de::Error::duplicate_field("segment_threshold"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            segment_threshold = Some(());
+                            global_config.segment_threshold = val.0;
+                        }
+                        Field::DownloadWorkers => {
+                            if download_workers.is_some() {
+                                return Err(de::Error::duplicate_field("download_workers"));
+                            }
+                            let val = map.next_value()?;
+                            download_workers = Some(());
+                            global_config.download_workers = val;
+                        }
+                        Field::PrefetchPages => {
+                            if prefetch_pages.is_some() {
+                                return Err(de::Error::duplicate_field("prefetch_pages"));
+                            }
+                            let val = map.next_value()?;
+                            prefetch_pages = Some(());
+                            global_config.prefetch_pages = val;
+                        }
+                        Field::CrossDuplicates => {
+                            if cross_duplicates.is_some() {
+                                return Err(de::Error::duplicate_field("cross_duplicates"));
+                            }
+                            let val: CrossDuplicate = map.next_value()?;
+                            cross_duplicates = Some(());
+                            global_config.cross_duplicates = val;
+                        }
+                        Field::NearDistance => {
+                            if near_distance.is_some() {
+                                return Err(de::Error::duplicate_field("near_distance"));
+                            }
+                            let val: u32 = map.next_value()?;
+                            near_distance = Some(());
+                            global_config.near_distance = val;
+                        }
+                        Field::SkipNearDuplicates => {
+                            if skip_near_duplicates.is_some() {
+                                return Err(de::Error::duplicate_field("skip_near_duplicates"));
+                            }
+                            let val: bool = map.next_value()?;
+                            skip_near_duplicates = Some(());
+                            global_config.skip_near_duplicates = val;
+                        }
                         Field::Yandere => {
                             if yandere {
                                 return Err(de::Error::duplicate_field("yandere"));
@@ -370,6 +652,24 @@ impl<'de> Deserialize<'de> for Config {
             "dirname_repl",
             "tags",
             "blacklist",
+            "max_retry_sleep",
+            "proxy",
+            "headers",
+            "user_agent",
+            "connect_timeout",
+            "read_timeout",
+            "http_version",
+            "ca_bundle",
+            "reserve",
+            "low_space",
+            "budget",
+            "segments",
+            "segment_threshold",
+            "download_workers",
+            "prefetch_pages",
+            "cross_duplicates",
+            "near_distance",
+            "skip_near_duplicates",
             "yandere",
             "sakugabooru",
             "konachan",
@@ -398,10 +698,28 @@ pub_struct!(GlobalConfig {
     dirname_repl: Vec<String>,
     tags: Vec<String>,
     blacklist: Vec<String>,
+    max_retry_sleep: f32,
+    proxy: Option<Proxy>,
+    headers: BTreeMap<String, String>,
+    user_agent: String,
+    connect_timeout: f32,
+    read_timeout: f32,
+    http_version: Option<String>,
+    ca_bundle: Option<String>,
+    reserve: u64,
+    low_space: LowSpace,
+    budget: Option<u64>,
+    segments: usize,
+    segment_threshold: u64,
+    download_workers: usize,
+    prefetch_pages: usize,
+    cross_duplicates: CrossDuplicate,
+    near_distance: u32,
+    skip_near_duplicates: bool,
 });
 
 #[allow(unused_assignments)]
-impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
+impl<'de> DeserializeSeed<'de> for &GlobalConfig {
     type Value = PlatformConfig;
 
     fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
@@ -428,6 +746,26 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
             DirnameRepl,
             Tags,
             Blacklist,
+            MaxRetrySleep,
+            Proxy,
+            Headers,
+            Cookies,
+            CookieFile,
+            UserAgent,
+            ConnectTimeout,
+            ReadTimeout,
+            HttpVersion,
+            CaBundle,
+            Reserve,
+            LowSpace,
+            Budget,
+            Segments,
+            SegmentThreshold,
+            DownloadWorkers,
+            PrefetchPages,
+            CrossDuplicates,
+            NearDistance,
+            SkipNearDuplicates,
             ApiKey,
             UserId,
         }
@@ -461,6 +799,26 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
                 let mut dirname_repl: Option<Vec<String>> = None;
                 let mut tags: Option<Vec<String>> = None;
                 let mut blacklist: Option<Vec<String>> = None;
+                let mut max_retry_sleep: Option<f32> = None;
+                let mut proxy: Option<Option<Proxy>> = None;
+                let mut headers: Option<BTreeMap<String, String>> = None;
+                let mut cookies: Option<BTreeMap<String, String>> = None;
+                let mut cookie_file: Option<String> = None;
+                let mut user_agent: Option<String> = None;
+                let mut connect_timeout: Option<f32> = None;
+                let mut read_timeout: Option<f32> = None;
+                let mut http_version: Option<Option<String>> = None;
+                let mut ca_bundle: Option<String> = None;
+                let mut reserve: Option<u64> = None;
+                let mut low_space: Option<LowSpace> = None;
+                let mut budget: Option<u64> = None;
+                let mut segments: Option<usize> = None;
+                let mut segment_threshold: Option<u64> = None;
+                let mut download_workers: Option<usize> = None;
+                let mut prefetch_pages: Option<usize> = None;
+                let mut cross_duplicates: Option<CrossDuplicate> = None;
+                let mut near_distance: Option<u32> = None;
+                let mut skip_near_duplicates: Option<bool> = None;
                 let mut api_key: Option<String> = None;
                 let mut user_id: Option<u64> = None;
 
@@ -491,8 +849,8 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
                             if database.is_some() {
                                 return Err(de::Error::duplicate_field("database"));
                             }
-                            let val = map.next_value()?;
-                            database = Some(val);
+                            let val: String = map.next_value()?;
+                            database = Some(expand_home(val));
                         }
                         Field::TargetDir => {
                             if target_dir.is_some() {
@@ -591,6 +949,146 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
                             let val = map.next_value()?;
                             blacklist = Some(val);
                         }
+                        Field::MaxRetrySleep => {
+                            if max_retry_sleep.is_some() {
+                                return Err(de::Error::duplicate_field("max_retry_sleep"));
+                            }
+                            let val = map.next_value()?;
+                            max_retry_sleep = Some(val);
+                        }
+                        Field::Proxy => {
+                            if proxy.is_some() {
+                                return Err(de::Error::duplicate_field("proxy"));
+                            }
+                            let val: Option<Proxy> = map.next_value()?;
+                            proxy = Some(val);
+                        }
+                        Field::Headers => {
+                            if headers.is_some() {
+                                return Err(de::Error::duplicate_field("headers"));
+                            }
+                            let val = map.next_value()?;
+                            headers = Some(val);
+                        }
+                        Field::Cookies => {
+                            if cookies.is_some() {
+                                return Err(de::Error::duplicate_field("cookies"));
+                            }
+                            let val = map.next_value()?;
+                            cookies = Some(val);
+                        }
+                        Field::CookieFile => {
+                            if cookie_file.is_some() {
+                                return Err(de::Error::duplicate_field("cookie_file"));
+                            }
+                            let val: String = map.next_value()?;
+                            cookie_file = Some(expand_home(val));
+                        }
+                        Field::UserAgent => {
+                            if user_agent.is_some() {
+                                return Err(de::Error::duplicate_field("user_agent"));
+                            }
+                            let val = map.next_value()?;
+                            user_agent = Some(val);
+                        }
+                        Field::ConnectTimeout => {
+                            if connect_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("connect_timeout"));
+                            }
+                            let val = map.next_value()?;
+                            connect_timeout = Some(val);
+                        }
+                        Field::ReadTimeout => {
+                            if read_timeout.is_some() {
+                                return Err(de::Error::duplicate_field("read_timeout"));
+                            }
+                            let val = map.next_value()?;
+                            read_timeout = Some(val);
+                        }
+                        Field::HttpVersion => {
+                            if http_version.is_some() {
+                                return Err(de::Error::duplicate_field("http_version"));
+                            }
+                            let val = map.next_value()?;
+                            http_version = Some(val);
+                        }
+                        Field::CaBundle => {
+                            if ca_bundle.is_some() {
+                                return Err(de::Error::duplicate_field("ca_bundle"));
+                            }
+                            let val: String = map.next_value()?;
+                            ca_bundle = Some(expand_home(val));
+                        }
+                        Field::Reserve => {
+                            if reserve.is_some() {
+                                return Err(de::Error::duplicate_field("reserve"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            reserve = Some(val.0);
+                        }
+                        Field::LowSpace => {
+                            if low_space.is_some() {
+                                return Err(de::Error::duplicate_field("low_space"));
+                            }
+                            let val = map.next_value()?;
+                            low_space = Some(val);
+                        }
+                        Field::Budget => {
+                            if budget.is_some() {
+                                return Err(de::Error::duplicate_field("budget"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            budget = Some(val.0);
+                        }
+                        Field::Segments => {
+                            if segments.is_some() {
+                                return Err(de::Error::duplicate_field("segments"));
+                            }
+                            let val = map.next_value()?;
+                            segments = Some(val);
+                        }
+                        Field::SegmentThreshold => {
+                            if segment_threshold.is_some() {
+                                return Err(de::Error::duplicate_field("segment_threshold"));
+                            }
+                            let val: ByteSize = map.next_value()?;
+                            segment_threshold = Some(val.0);
+                        }
+                        Field::DownloadWorkers => {
+                            if download_workers.is_some() {
+                                return Err(de::Error::duplicate_field("download_workers"));
+                            }
+                            let val = map.next_value()?;
+                            download_workers = Some(val);
+                        }
+                        Field::PrefetchPages => {
+                            if prefetch_pages.is_some() {
+                                return Err(de::Error::duplicate_field("prefetch_pages"));
+                            }
+                            let val = map.next_value()?;
+                            prefetch_pages = Some(val);
+                        }
+                        Field::CrossDuplicates => {
+                            if cross_duplicates.is_some() {
+                                return Err(de::Error::duplicate_field("cross_duplicates"));
+                            }
+                            let val: CrossDuplicate = map.next_value()?;
+                            cross_duplicates = Some(val);
+                        }
+                        Field::NearDistance => {
+                            if near_distance.is_some() {
+                                return Err(de::Error::duplicate_field("near_distance"));
+                            }
+                            let val: u32 = map.next_value()?;
+                            near_distance = Some(val);
+                        }
+                        Field::SkipNearDuplicates => {
+                            if skip_near_duplicates.is_some() {
+                                return Err(de::Error::duplicate_field("skip_near_duplicates"));
+                            }
+                            let val: bool = map.next_value()?;
+                            skip_near_duplicates = Some(val);
+                        }
                         Field::ApiKey => {
                             if api_key.is_some() {
                                 return Err(de::Error::duplicate_field("api_key"));
@@ -609,7 +1107,7 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
                 }
                 Ok(PlatformConfig {
                     to_cloud: to_cloud.unwrap_or(self.0.to_cloud),
-                    delete: delete.unwrap_or(self.0.delete.clone()),
+                    delete: delete.unwrap_or(self.0.delete),
                     cloud: match to_cloud {
                         Some(b) => match b {
                             true => match cloud {
@@ -652,15 +1150,36 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
                         Some(c) => Some(c),
                         None => self.0.compress.clone(),
                     },
-                    skip: skip.unwrap_or(self.0.skip.clone()),
-                    sleep: sleep.unwrap_or(self.0.sleep.clone()),
-                    retries: retries.unwrap_or(self.0.retries.clone()),
-                    retry_sleep: retry_sleep.unwrap_or(self.0.retry_sleep.clone()),
-                    timeout: timeout.unwrap_or(self.0.timeout.clone()),
+                    skip: skip.unwrap_or(self.0.skip),
+                    sleep: sleep.unwrap_or(self.0.sleep),
+                    retries: retries.unwrap_or(self.0.retries),
+                    retry_sleep: retry_sleep.unwrap_or(self.0.retry_sleep),
+                    timeout: timeout.unwrap_or(self.0.timeout),
                     filename_repl: filename_repl.unwrap_or(self.0.filename_repl.clone()),
                     dirname_repl: dirname_repl.unwrap_or(self.0.dirname_repl.clone()),
                     tags: tags.unwrap_or(self.0.tags.clone()),
                     blacklist: blacklist.unwrap_or(self.0.blacklist.clone()),
+                    max_retry_sleep: max_retry_sleep.unwrap_or(self.0.max_retry_sleep),
+                    proxy: proxy.unwrap_or(self.0.proxy.clone()),
+                    headers: headers.unwrap_or(self.0.headers.clone()),
+                    cookies: cookies.unwrap_or_default(),
+                    cookie_file,
+                    user_agent: user_agent.unwrap_or(self.0.user_agent.clone()),
+                    connect_timeout: connect_timeout.unwrap_or(self.0.connect_timeout),
+                    read_timeout: read_timeout.unwrap_or(self.0.read_timeout),
+                    http_version: http_version.unwrap_or(self.0.http_version.clone()),
+                    ca_bundle: ca_bundle.or(self.0.ca_bundle.clone()),
+                    reserve: reserve.unwrap_or(self.0.reserve),
+                    low_space: low_space.unwrap_or(self.0.low_space),
+                    budget,
+                    segments: segments.unwrap_or(self.0.segments),
+                    segment_threshold: segment_threshold.unwrap_or(self.0.segment_threshold),
+                    download_workers: download_workers.unwrap_or(self.0.download_workers),
+                    prefetch_pages: prefetch_pages.unwrap_or(self.0.prefetch_pages),
+                    cross_duplicates: cross_duplicates.unwrap_or(self.0.cross_duplicates),
+                    near_distance: near_distance.unwrap_or(self.0.near_distance),
+                    skip_near_duplicates: skip_near_duplicates
+                        .unwrap_or(self.0.skip_near_duplicates),
                     api_key,
                     user_id,
                 })
@@ -683,6 +1202,26 @@ impl<'de, 'a> DeserializeSeed<'de> for &'a GlobalConfig {
             "filename_repl",
             "dirname_repl",
             "tags",
+            "max_retry_sleep",
+            "proxy",
+            "headers",
+            "cookies",
+            "cookie_file",
+            "user_agent",
+            "connect_timeout",
+            "read_timeout",
+            "http_version",
+            "ca_bundle",
+            "reserve",
+            "low_space",
+            "budget",
+            "segments",
+            "segment_threshold",
+            "download_workers",
+            "prefetch_pages",
+            "cross_duplicates",
+            "near_distance",
+            "skip_near_duplicates",
             "api_key",
             "user_id",
         ];
@@ -716,6 +1255,24 @@ impl Default for GlobalConfig {
                 .collect(),
             tags: Vec::new(),
             blacklist: Vec::new(),
+            max_retry_sleep: 60.0,
+            proxy: None,
+            headers: BTreeMap::new(),
+            user_agent: "booruchan/0.1.0".into(),
+            connect_timeout: 10.0,
+            read_timeout: 30.0,
+            http_version: None,
+            ca_bundle: None,
+            reserve: 1 << 30,
+            low_space: LowSpace::Pause,
+            budget: None,
+            segments: 4,
+            segment_threshold: 16 << 20,
+            download_workers: 2,
+            prefetch_pages: 1,
+            cross_duplicates: CrossDuplicate::Download,
+            near_distance: 10,
+            skip_near_duplicates: false,
         }
     }
 }
@@ -738,6 +1295,26 @@ pub_struct!(PlatformConfig {
     dirname_repl: Vec<String>,
     tags: Vec<String>,
     blacklist: Vec<String>,
+    max_retry_sleep: f32,
+    proxy: Option<Proxy>,
+    headers: BTreeMap<String, String>,
+    cookies: BTreeMap<String, String>,
+    cookie_file: Option<String>,
+    user_agent: String,
+    connect_timeout: f32,
+    read_timeout: f32,
+    http_version: Option<String>,
+    ca_bundle: Option<String>,
+    reserve: u64,
+    low_space: LowSpace,
+    budget: Option<u64>,
+    segments: usize,
+    segment_threshold: u64,
+    download_workers: usize,
+    prefetch_pages: usize,
+    cross_duplicates: CrossDuplicate,
+    near_distance: u32,
+    skip_near_duplicates: bool,
     api_key: Option<String>,
     user_id: Option<u64>,
 });
@@ -0,0 +1,361 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+// crate
+use reqwest::{
+    header::{HeaderMap, HeaderValue, SET_COOKIE},
+    Url,
+};
+
+// local
+use crate::{statics::PROGRESS, utils};
+
+/// cookie jar that can be loaded from and saved to a netscape format cookie file
+/// (the format used by curl, wget and browser extensions).
+pub struct CookieJar {
+    file: Option<PathBuf>,
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+#[derive(Debug, Clone)]
+struct Cookie {
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    // unix timestamp, 0 for session cookies
+    expires: u64,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url, now: u64) -> bool {
+        let host = match url.host_str() {
+            Some(h) => h,
+            None => return false,
+        };
+        let domain = self.domain.trim_start_matches('.');
+        let domain_match =
+            host == domain || (self.include_subdomains && domain_matches(host, domain));
+        return domain_match
+            && url.path().starts_with(self.path.as_str())
+            && (!self.secure || url.scheme() == "https")
+            && (self.expires == 0 || self.expires > now);
+    }
+
+    fn is_same(&self, other: &Cookie) -> bool {
+        self.name == other.name
+            && self.path == other.path
+            && self.domain.trim_start_matches('.') == other.domain.trim_start_matches('.')
+    }
+
+    /// `domain  include_subdomains  path  secure  expires  name  value`
+    fn parse_line(line: &str) -> Option<Self> {
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') || line.trim().is_empty() {
+            return None;
+        }
+        let fields = line.split('\t').collect::<Vec<&str>>();
+        if fields.len() != 7 {
+            return None;
+        }
+        Some(Self {
+            domain: fields[0].to_string(),
+            include_subdomains: fields[1].eq_ignore_ascii_case("TRUE"),
+            path: fields[2].to_string(),
+            secure: fields[3].eq_ignore_ascii_case("TRUE"),
+            expires: fields[4].parse::<u64>().unwrap_or(0),
+            name: fields[5].to_string(),
+            value: fields[6].trim_end_matches(['\r', '\n']).to_string(),
+        })
+    }
+
+    fn to_line(&self) -> String {
+        let bool_str = |b: bool| if b { "TRUE" } else { "FALSE" };
+        format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            self.domain,
+            bool_str(self.include_subdomains),
+            self.path,
+            bool_str(self.secure),
+            self.expires,
+            self.name,
+            self.value
+        )
+    }
+
+    /// parses a `Set-Cookie` header value received from `url`
+    fn parse_set_cookie(header: &str, url: &Url, now: u64) -> Option<Self> {
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.trim().split_once('=')?;
+        let mut cookie = Self {
+            domain: url.host_str()?.to_string(),
+            include_subdomains: false,
+            path: "/".to_string(),
+            secure: false,
+            expires: 0,
+            name: name.trim().to_string(),
+            value: value.trim().to_string(),
+        };
+        let mut max_age: Option<i64> = None;
+        for attr in parts {
+            let (key, val) = match attr.trim().split_once('=') {
+                Some((k, v)) => (k.trim(), v.trim()),
+                None => (attr.trim(), ""),
+            };
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !val.trim_start_matches('.').is_empty() => {
+                    let domain = val.trim_start_matches('.').to_ascii_lowercase();
+                    // a site can't set cookies for other sites
+                    if !domain_matches(cookie.domain.as_str(), domain.as_str()) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.include_subdomains = true;
+                }
+                "path" if val.starts_with('/') => cookie.path = val.to_string(),
+                "secure" => cookie.secure = true,
+                "expires" => {
+                    if let Ok(date) = httpdate::parse_http_date(val) {
+                        cookie.expires = unix_time(date).max(1);
+                    }
+                }
+                "max-age" => max_age = val.parse::<i64>().ok(),
+                _ => (),
+            }
+        }
+        // max-age takes precedence over expires
+        match max_age {
+            Some(age) if age <= 0 => cookie.expires = 1,
+            Some(age) => cookie.expires = now + age as u64,
+            None => (),
+        }
+        return Some(cookie);
+    }
+}
+
+impl CookieJar {
+    /// loads `file` if it exists, `cookies` from the config are set for `url`'s domain.
+    pub fn new(file: Option<PathBuf>, cookies: &BTreeMap<String, String>, url: &str) -> Self {
+        let mut jar: Vec<Cookie> = Vec::new();
+        if let Some(ref path) = file {
+            match fs::read_to_string(path) {
+                Ok(content) => jar.extend(content.lines().filter_map(Cookie::parse_line)),
+                Err(e) if e.kind() == ErrorKind::NotFound => (),
+                Err(e) => eprintln!("unable to read cookie file {}: {e}", path.display()),
+            }
+        }
+        if let Some(host) = Url::parse(url).ok().as_ref().and_then(|u| u.host_str()) {
+            for (name, value) in cookies.iter() {
+                let cookie = Cookie {
+                    domain: host.to_string(),
+                    include_subdomains: true,
+                    path: "/".to_string(),
+                    secure: false,
+                    expires: 0,
+                    name: name.clone(),
+                    value: value.clone(),
+                };
+                jar.retain(|c| !c.is_same(&cookie));
+                jar.push(cookie);
+            }
+        }
+        Self {
+            file,
+            cookies: Mutex::new(jar),
+        }
+    }
+
+    /// value for the `Cookie` header of a request to `url`
+    pub fn header(&self, url: &Url) -> Option<HeaderValue> {
+        let now = unix_time(SystemTime::now());
+        let cookies = self.cookies.lock().unwrap();
+        let value = cookies
+            .iter()
+            .filter(|c| c.matches(url, now))
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<String>>()
+            .join("; ");
+        match value.is_empty() {
+            true => None,
+            false => HeaderValue::from_str(value.as_str()).ok(),
+        }
+    }
+
+    /// stores cookies from the `Set-Cookie` headers of a response
+    pub fn store(&self, url: &Url, headers: &HeaderMap) {
+        let now = unix_time(SystemTime::now());
+        let mut cookies = self.cookies.lock().unwrap();
+        for value in headers.get_all(SET_COOKIE) {
+            let cookie = match value
+                .to_str()
+                .ok()
+                .and_then(|v| Cookie::parse_set_cookie(v, url, now))
+            {
+                Some(c) => c,
+                None => continue,
+            };
+            cookies.retain(|c| !c.is_same(&cookie));
+            if cookie.expires == 0 || cookie.expires > now {
+                cookies.push(cookie);
+            }
+        }
+    }
+
+    /// writes the jar back to the cookie file, expired cookies are dropped.
+    pub fn save(&self) {
+        let path = match self.file {
+            Some(ref p) => p,
+            None => return,
+        };
+        let now = unix_time(SystemTime::now());
+        let mut content = String::from("# Netscape HTTP Cookie File\n");
+        for cookie in self.cookies.lock().unwrap().iter() {
+            if cookie.expires == 0 || cookie.expires > now {
+                content.push_str(cookie.to_line().as_str());
+                content.push('\n');
+            }
+        }
+        if let Some(parent) = path.parent() {
+            utils::recursive_dir_create_blocking(parent).ok();
+        }
+        match fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+        {
+            Ok(mut f) => {
+                if let Err(e) = f.write_all(content.as_bytes()) {
+                    PROGRESS.eprintln(format!(
+                        "unable to write cookie file {}: {e}",
+                        path.display()
+                    ));
+                }
+            }
+            Err(e) => PROGRESS.eprintln(format!(
+                "unable to open cookie file {}: {e}",
+                path.display()
+            )),
+        }
+    }
+}
+
+/// `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+    match host.strip_suffix(domain) {
+        Some("") => return true,
+        Some(prefix) => return prefix.ends_with('.'),
+        None => return false,
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn cookie_file_lines() {
+        let line = "yande.re\tTRUE\t/\tTRUE\t1800000000\tsession\tabc=def";
+        let cookie = Cookie::parse_line(line).unwrap();
+        assert_eq!(cookie.domain, "yande.re");
+        assert!(cookie.include_subdomains && cookie.secure);
+        assert_eq!(cookie.expires, 1_800_000_000);
+        assert_eq!(
+            (cookie.name.as_str(), cookie.value.as_str()),
+            ("session", "abc=def")
+        );
+        assert_eq!(cookie.to_line(), line);
+
+        let cookie =
+            Cookie::parse_line("#HttpOnly_.konachan.com\tTRUE\t/\tFALSE\t0\tid\t1\r\n").unwrap();
+        assert_eq!(cookie.domain, ".konachan.com");
+        assert_eq!(cookie.value, "1");
+        assert!(cookie.matches(&url("http://www.konachan.com/post"), NOW));
+        assert_eq!(cookie.to_line(), ".konachan.com\tTRUE\t/\tFALSE\t0\tid\t1");
+
+        assert!(Cookie::parse_line("# Netscape HTTP Cookie File").is_none());
+        assert!(Cookie::parse_line("").is_none());
+        assert!(Cookie::parse_line("yande.re\tTRUE\t/\tFALSE\t0\tname").is_none());
+    }
+
+    #[test]
+    fn expired_cookies() {
+        let expired = Cookie::parse_line("yande.re\tFALSE\t/\tFALSE\t1600000000\ta\tb").unwrap();
+        assert!(!expired.matches(&url("https://yande.re/"), NOW));
+        let session = Cookie::parse_line("yande.re\tFALSE\t/\tFALSE\t0\ta\tb").unwrap();
+        assert!(session.matches(&url("https://yande.re/"), NOW));
+
+        let from = url("https://yande.re/post.json");
+        let cookie = Cookie::parse_set_cookie("a=b; Max-Age=0", &from, NOW).unwrap();
+        assert!(!cookie.matches(&from, NOW));
+        let cookie =
+            Cookie::parse_set_cookie("a=b; Expires=Sun, 06 Nov 1994 08:49:37 GMT", &from, NOW)
+                .unwrap();
+        assert!(!cookie.matches(&from, NOW));
+        // max-age wins over expires
+        let cookie = Cookie::parse_set_cookie(
+            "a=b; Expires=Sun, 06 Nov 1994 08:49:37 GMT; Max-Age=60",
+            &from,
+            NOW,
+        )
+        .unwrap();
+        assert_eq!(cookie.expires, NOW + 60);
+    }
+
+    #[test]
+    fn set_cookie_attributes() {
+        let from = url("https://www.yande.re/post.json");
+        let cookie = Cookie::parse_set_cookie("a=b", &from, NOW).unwrap();
+        assert_eq!(cookie.domain, "www.yande.re");
+        assert!(!cookie.include_subdomains);
+        assert!(!cookie.matches(&url("https://img.yande.re/"), NOW));
+
+        let cookie = Cookie::parse_set_cookie(
+            " a = b ; Domain=.Yande.re; Path=/post; Secure; HttpOnly",
+            &from,
+            NOW,
+        )
+        .unwrap();
+        assert_eq!((cookie.name.as_str(), cookie.value.as_str()), ("a", "b"));
+        assert_eq!(cookie.domain, "yande.re");
+        assert_eq!(cookie.path, "/post");
+        assert!(cookie.include_subdomains && cookie.secure);
+        assert!(cookie.matches(&url("https://files.yande.re/post/1"), NOW));
+        assert!(!cookie.matches(&url("http://yande.re/post"), NOW));
+        assert!(!cookie.matches(&url("https://yande.re/pool"), NOW));
+
+        assert!(Cookie::parse_set_cookie("no value", &from, NOW).is_none());
+    }
+
+    #[test]
+    fn set_cookie_rejects_other_domains() {
+        let from = url("https://yande.re/post.json");
+        assert!(Cookie::parse_set_cookie("a=b; Domain=konachan.com", &from, NOW).is_none());
+        assert!(Cookie::parse_set_cookie("a=b; Domain=e.re", &from, NOW).is_none());
+        assert!(Cookie::parse_set_cookie("a=b; Domain=www.yande.re", &from, NOW).is_none());
+        assert!(Cookie::parse_set_cookie("a=b; Domain=yande.re", &from, NOW).is_some());
+        // a leading dot alone isn't a domain, the cookie is host only
+        let cookie = Cookie::parse_set_cookie("a=b; Domain=.", &from, NOW).unwrap();
+        assert!(!cookie.include_subdomains);
+    }
+}
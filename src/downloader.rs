@@ -4,183 +4,188 @@ use std::os::android::fs::MetadataExt;
 use std::os::linux::fs::MetadataExt;
 
 use std::{
+    ffi::OsString,
     io::{ErrorKind, SeekFrom},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::SystemTime,
 };
 
 use bytes::BytesMut;
-use futures::TryStreamExt;
+use futures::{future::join_all, TryStreamExt};
 use reqwest::{
-    header::{HeaderMap, HeaderValue, RANGE},
-    Client, StatusCode,
+    header::{HeaderMap, HeaderValue, CONTENT_RANGE, RANGE},
+    StatusCode,
 };
 use tokio::{
-    fs::{metadata, File, OpenOptions},
+    fs::{self, metadata, File, OpenOptions},
     io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
-    time::{sleep, Duration},
+    time::Duration,
 };
 use tokio_util::io::StreamReader;
 
 use crate::{
-    consts::{BLOCKSIZE, GREEN, RESET},
-    utils,
+    client::Session,
+    consts::{BLOCKSIZE, GREEN, PART_EXT, PART_MAX_AGE, RESET, SEGMENTS_EXT},
+    progress::DownloadHandle,
+    retry::{self, RetryPolicy},
+    statics::PROGRESS,
+    utils, Error,
 };
 
-/*struct ProgressBar<'p> {
-    file: &'p PathBuf,
-    total_size: &'p usize,
-    status: &'p Status,
-}
-
-impl<'p> ProgressBar<'p> {
-    pub fn new(file: &'p PathBuf, total_size: &'p usize, status: &'p Status) -> Self {
-        Self {
-            file,
-            total_size,
-            status,
-        }
-    }
-
-    pub async fn run(&self) {
-        let dur = Duration::from_secs(1);
-        while let Status::Running = *self.status {
-            match metadata(self.file).await {
-                Ok(m) => println!(
-                    "{}: {}/{}",
-                    self.file.display(),
-                    m.st_size(),
-                    self.total_size
-                ),
-                Err(_) => (),
-            }
-            sleep(dur).await;
-        }
-        return;
-    }
-}
-
-#[derive(Clone, Copy)]
-enum Status {
-    Running,
-    Done,
-}
-*/
 pub struct Downloader<'d> {
-    pub client: Client,
+    pub session: &'d Session,
     pub url: &'d str,
     pub dest: Vec<&'d str>,
     pub fallback: Option<&'d str>,
+    /// total timeout of the range probe, transfers are only bounded by the client's
+    /// `read_timeout` so large files aren't cut off
     pub timeout: Duration,
-    pub retries: i64,
-    pub retry_sleep: Duration,
-    tries: i64,
-    written: usize,
+    pub retry: RetryPolicy,
+    pub expected_size: Option<usize>,
+    pub md5: Option<&'d str>,
+    /// number of concurrent ranged requests for files of at least `segment_threshold` bytes
+    pub segments: usize,
+    pub segment_threshold: u64,
     target_size: usize,
     target_file: PathBuf,
-    //status: Rc<RefCell<Status>>,
+    part_file: PathBuf,
+    // bytes received by this download, resumed parts aren't counted
+    transferred: u64,
 }
 
 impl<'d> Downloader<'d> {
     pub fn new(
-        client: Client,
+        session: &'d Session,
         url: &'d str,
         dest: Vec<&'d str>,
         fallback: Option<&'d str>,
         timeout: Duration,
-        retries: i64,
-        retry_sleep: Duration,
+        retry: RetryPolicy,
     ) -> Self {
+        let target_file = dest.iter().collect::<PathBuf>();
         Self {
-            client,
+            session,
             url,
-            target_file: dest.iter().collect::<PathBuf>(),
+            part_file: part_path(&target_file),
+            target_file,
             dest,
             fallback,
             timeout,
-            retries,
-            retry_sleep,
-            tries: 0,
-            written: 0,
+            retry,
+            expected_size: None,
+            md5: None,
+            segments: 1,
+            segment_threshold: u64::MAX,
             target_size: 0,
-            //status: Rc::new(RefCell::new(Status::Running)),
+            transferred: 0,
         }
     }
 
-    async fn resolve_target_file(&mut self) -> () {
-        match metadata(&self.target_file).await {
-            Ok(metadata) => {
-                self.target_size = metadata.st_size() as usize;
-                return;
-            }
-            Err(_) => match self.target_file.parent() {
-                Some(parent) => match utils::recursive_dir_create(parent).await {
-                    Ok(_) => return,
-                    Err(e) => match e.kind() {
-                        ErrorKind::PermissionDenied => match self.fallback {
-                            Some(fallback) => {
-                                if self.dest.len() > 1 {
-                                    self.dest[0] = fallback;
-                                    self.target_file = self.dest.iter().collect::<PathBuf>();
-                                    match utils::recursive_dir_create(&self.target_file).await {
-                                        Ok(_) => return,
-                                        Err(e) => panic!("{e:?}"),
-                                    }
-                                } else {
-                                    todo!()
-                                }
-                            }
-                            None => panic!("{e:?}"),
-                        },
-                        _ => panic!("{e:?}"),
-                    },
-                },
-                None => return,
-            },
-        }
+    /// size reported by the api, used when the server doesn't send `Content-Length`
+    pub fn with_expected_size(mut self, size: Option<usize>) -> Self {
+        self.expected_size = size;
+        self
+    }
+
+    /// checksum the finished download is verified against
+    pub fn with_md5(mut self, md5: Option<&'d str>) -> Self {
+        self.md5 = md5;
+        self
+    }
+
+    /// splits large files into `segments` ranged requests fetched concurrently
+    pub fn with_segments(mut self, segments: usize, threshold: u64) -> Self {
+        self.segments = segments;
+        self.segment_threshold = threshold;
+        self
     }
 
-    /*async fn progress_bar(&self) {
-        //let target_file: Ref<'_, PathBuf> = _target_file.borrow();
-        let dur = Duration::from_secs(1);
-        while let Status::Running = self.status {
-            println!(
-                "{}: {}/{}",
-                self.target_file.borrow().display(),
-                self.target_size,
-                self.total_size
-            );
-            sleep(self.retry_sleep).await
+    async fn resolve_target_file(&mut self) -> Result<(), Error> {
+        if let Ok(metadata) = metadata(&self.part_file).await {
+            self.target_size = metadata.st_size() as usize;
+            return Ok(());
         }
-        return;
-    }*/
-
-    pub async fn download(mut self) -> Option<PathBuf> {
-        let mut on_download_failure = move || {
-            if self.tries >= 0 {
-                if self.tries == self.retries {
-                    println!("max number of retries reached for '{url}'", url = self.url);
-                    return Err(());
-                } else {
-                    self.tries += 1;
+        self.target_size = 0;
+        let parent = match self.target_file.parent() {
+            Some(p) => p.to_path_buf(),
+            None => return Ok(()),
+        };
+        match utils::recursive_dir_create(&parent).await {
+            Ok(_) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => match self.fallback {
+                Some(fallback) if self.dest.len() > 1 => {
+                    self.dest[0] = fallback;
+                    self.target_file = self.dest.iter().collect::<PathBuf>();
+                    self.part_file = part_path(&self.target_file);
+                    if let Some(parent) = self.target_file.parent() {
+                        utils::recursive_dir_create(parent)
+                            .await
+                            .map_err(|e| Error::file(parent, e))?;
+                    }
                     return Ok(());
                 }
+                _ => return Err(Error::file(parent, e)),
+            },
+            Err(e) => return Err(Error::file(parent, e)),
+        }
+    }
+
+    /// downloads into `<target>.part` and renames it to the target once it's verified.
+    /// returns the file and the number of bytes received, which is 0 when the file exists.
+    pub async fn download(mut self) -> Result<(PathBuf, u64), Error> {
+        self.resolve_target_file().await?;
+        if metadata(&self.target_file).await.is_ok() && self.verify(&self.target_file).await {
+            PROGRESS.println(format!("exists: {}", self.target_file.display()));
+            return Ok((self.target_file, 0));
+        }
+        let mut backoff = self.retry.backoff();
+        let bar = PROGRESS.download(match self.target_file.file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => self.url.to_string(),
+        });
+        loop {
+            if !self.use_segments().await || !self.fetch_segments(&mut backoff, &bar).await? {
+                self.fetch(&mut backoff, &bar).await?;
+            }
+            if self.verify(&self.part_file).await {
+                break;
+            }
+            self.truncate().await?;
+            fs::remove_file(segments_path(&self.part_file)).await.ok();
+            if let Err(e) = backoff.wait("checksum mismatch", None).await {
+                fs::remove_file(&self.part_file).await.ok();
+                return Err(e.into());
+            }
+        }
+        match fs::rename(&self.part_file, &self.target_file).await {
+            Ok(_) => {
+                PROGRESS.println(format!("{GREEN}{}{RESET}", self.target_file.display()));
+                return Ok((self.target_file, self.transferred));
+            }
+            Err(e) => return Err(Error::file(&self.target_file, e)),
+        }
+    }
+
+    /// streams the response into the part file, returns when the transfer is complete.
+    async fn fetch(
+        &mut self,
+        backoff: &mut retry::Backoff,
+        bar: &DownloadHandle,
+    ) -> Result<(), Error> {
+        loop {
+            self.resolve_target_file().await?;
+            // konachan does not return content_length header when bytes == content_length,
+            // no need to ask for an empty range when we already know the size.
+            if self
+                .expected_size
+                .is_some_and(|size| size > 0 && size == self.target_size)
+            {
+                return Ok(());
             }
-            return Ok(());
-        };
-        /*let status = Status::Running;
-        let handle = {
-            let target_file = &target_file;
-            let status = &status;
-            tokio::task::spawn_local(async move {
-                ProgressBar::new(target_file, &self.total_size, status)
-                    .run()
-                    .await
-            })
-        };*/
-        #[allow(unused_assignments)]
-        let mut is_success: bool = false;
-        'downloader: loop {
-            self.resolve_target_file().await;
             let mut headers = HeaderMap::new();
             if self.target_size > 0 {
                 headers.insert(
@@ -188,146 +193,618 @@ impl<'d> Downloader<'d> {
                     HeaderValue::from_str(format!("bytes={}-", self.target_size).as_str()).unwrap(),
                 );
             }
-            match self
-                .client
+            let resp = match self.session.get(self.url).headers(headers).send().await {
+                Ok(resp) => {
+                    self.session.store_cookies(&resp);
+                    resp
+                }
+                Err(e) => {
+                    backoff.wait(e, None).await?;
+                    continue;
+                }
+            };
+            if let Err(e) = resp.error_for_status_ref() {
+                let status_code = resp.status();
+                match status_code {
+                    StatusCode::RANGE_NOT_SATISFIABLE => {
+                        match content_range_total(resp.headers()) {
+                            Some(size) if size == self.target_size => return Ok(()),
+                            Some(size) => {
+                                // local file is bigger than the remote one, start over
+                                PROGRESS.eprintln(format!(
+                                    "{}: local size {} does not match remote size {size}",
+                                    self.part_file.display(),
+                                    self.target_size,
+                                ));
+                                self.truncate().await?;
+                                backoff.wait(e, None).await?;
+                                continue;
+                            }
+                            None => return Err(Error::Status(status_code, self.url.to_string())),
+                        }
+                    }
+                    _ if status_code.is_client_error() && !retry::is_throttled(status_code) => {
+                        return Err(Error::Status(status_code, self.url.to_string()))
+                    }
+                    _ => {
+                        let retry_after = match retry::is_throttled(status_code) {
+                            true => retry::retry_after(resp.headers()),
+                            false => None,
+                        };
+                        backoff.wait(e, retry_after).await?;
+                        continue;
+                    }
+                }
+            }
+            // servers that don't support ranges send the whole file with 200
+            let is_partial = resp.status() == StatusCode::PARTIAL_CONTENT;
+            if !is_partial && self.target_size > 0 {
+                self.truncate().await?;
+            }
+            // content_length may be missing for chunked responses,
+            // fall back to the size reported by the api when available.
+            let content_length: Option<usize> = match is_partial {
+                true => content_range_total(resp.headers())
+                    .or(resp.content_length().map(|l| l as usize + self.target_size)),
+                false => resp.content_length().map(|l| l as usize),
+            }
+            .or(self.expected_size.filter(|size| *size > 0));
+            let file: File = match OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&self.part_file)
+                .await
+            {
+                Ok(f) => f,
+                Err(e) => return Err(Error::file(&self.part_file, e)),
+            };
+            let mut writer = BufWriter::new(file);
+            writer
+                .seek(SeekFrom::End(0))
+                .await
+                .map_err(|e| Error::file(&self.part_file, e))?;
+            let mut buf: BytesMut = BytesMut::with_capacity(BLOCKSIZE);
+            let stream = resp.bytes_stream();
+            let mut reader = StreamReader::new(stream.map_err(std::io::Error::other));
+            bar.start(self.target_size as u64, content_length.map(|l| l as u64));
+            loop {
+                match reader.read_buf(&mut buf).await {
+                    Ok(0) => match content_length {
+                        None => return Ok(()),
+                        Some(l) if l == self.target_size => return Ok(()),
+                        Some(l) => {
+                            if self.target_size > l {
+                                self.truncate().await?;
+                            }
+                            backoff
+                                .wait("connection closed before completion", None)
+                                .await?;
+                            break;
+                        }
+                    },
+                    Ok(_) => {
+                        let n = writer
+                            .write(&buf)
+                            .await
+                            .map_err(|e| Error::file(&self.part_file, e))?;
+                        self.target_size += n;
+                        self.transferred += n as u64;
+                        writer
+                            .flush()
+                            .await
+                            .map_err(|e| Error::file(&self.part_file, e))?;
+                        buf.clear();
+                        bar.set(self.target_size as u64);
+                    }
+                    Err(e) => {
+                        backoff.wait(e, None).await?;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// a part file left by a single connection download is resumed as it is,
+    /// segmented downloads are only started fresh or resumed from their state file.
+    async fn use_segments(&self) -> bool {
+        if self.segments < 2
+            || self
+                .expected_size
+                .is_none_or(|size| (size as u64) < self.segment_threshold)
+        {
+            return false;
+        }
+        return self.target_size == 0 || metadata(segments_path(&self.part_file)).await.is_ok();
+    }
+
+    /// total size of the file when the server answers ranged requests, None when it doesn't.
+    async fn probe(&self, backoff: &mut retry::Backoff) -> Result<Option<u64>, Error> {
+        loop {
+            let resp = match self
+                .session
                 .get(self.url)
                 .timeout(self.timeout)
-                .headers(headers)
+                .header(RANGE, "bytes=0-0")
                 .send()
                 .await
             {
                 Ok(resp) => {
-                    match resp.error_for_status_ref() {
-                        Ok(_) => (),
-                        Err(e) => match e.status() {
-                            Some(status_code) if status_code.is_client_error() => match status_code
-                            {
-                                StatusCode::RANGE_NOT_SATISFIABLE => {
-                                    match resp.headers().get("content-range") {
-                                        Some(val) => match std::str::from_utf8(val.as_bytes())
-                                            .unwrap()
-                                            .rsplit_once('/')
-                                        {
-                                            Some(range) => match range.1.parse::<usize>() {
-                                                Ok(size) => {
-                                                    if size == self.target_size {
-                                                        is_success = true;
-                                                        break 'downloader;
-                                                    }
-                                                }
-                                                Err(_) => panic!(
-                                                    "PARSE ERROR: {}\n{e:?}\n{:?}",
-                                                    range.0,
-                                                    resp.headers()
-                                                ),
-                                            },
-                                            None => panic!("{e:?}\n{:?}", resp.headers()),
-                                        },
-                                        None => panic!("{e:?}\n{:?}", resp.headers()),
-                                    }
-                                    is_success = true;
-                                    break 'downloader;
-                                }
-                                _ => panic!("{e:?}\n{:?}", resp.headers()),
-                            },
-                            Some(_) => eprintln!("{e:?}\n{:?}", resp.headers()),
-                            None => todo!(),
-                        },
+                    self.session.store_cookies(&resp);
+                    resp
+                }
+                Err(e) => {
+                    backoff.wait(e, None).await?;
+                    continue;
+                }
+            };
+            let status = resp.status();
+            if status == StatusCode::PARTIAL_CONTENT {
+                return Ok(content_range_total(resp.headers()).map(|t| t as u64));
+            } else if status.is_success() {
+                return Ok(None);
+            } else if retry::is_throttled(status) {
+                backoff
+                    .wait(status, retry::retry_after(resp.headers()))
+                    .await?;
+            } else if status.is_client_error() {
+                return Err(Error::Status(status, self.url.to_string()));
+            } else {
+                backoff.wait(status, None).await?;
+            }
+        }
+    }
+
+    /// downloads the file with concurrent ranged requests into a preallocated part file.
+    /// progress of each segment is kept in `<file>.part.segments` so they can be resumed.
+    /// returns false when the server doesn't support ranges.
+    async fn fetch_segments(
+        &mut self,
+        backoff: &mut retry::Backoff,
+        bar: &DownloadHandle,
+    ) -> Result<bool, Error> {
+        let state_file = segments_path(&self.part_file);
+        let expected = self.expected_size.map(|s| s as u64);
+        let segments = match read_segments(&state_file, &self.part_file, expected).await {
+            Some(s) => s,
+            None => {
+                let total = match self.probe(backoff).await? {
+                    Some(t) if t > 0 => t,
+                    _ => return Ok(false),
+                };
+                let file = OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(true)
+                    .open(&self.part_file)
+                    .await
+                    .map_err(|e| Error::file(&self.part_file, e))?;
+                file.set_len(total)
+                    .await
+                    .map_err(|e| Error::file(&self.part_file, e))?;
+                let segments = Segment::split(total, self.segments);
+                write_segments(&state_file, &segments)?;
+                segments
+            }
+        };
+        let total: u64 = segments.iter().map(|s| s.len()).sum();
+        let resumed: u64 = segments.iter().map(|s| s.written).sum();
+        let written = AtomicU64::new(resumed);
+        bar.start(written.load(Ordering::Relaxed), Some(total));
+        let count = segments.len();
+        let state = Mutex::new(segments);
+        let this = &*self;
+        let results =
+            join_all((0..count).map(|i| this.fetch_segment(i, &state, &state_file, &written, bar)))
+                .await;
+        // keep whatever was written by the segments that made it
+        write_segments(&state_file, &state.lock().unwrap())?;
+        self.transferred += written.load(Ordering::Relaxed) - resumed;
+        for result in results {
+            result?;
+        }
+        fs::remove_file(&state_file).await.ok();
+        self.target_size = total as usize;
+        return Ok(true);
+    }
+
+    async fn fetch_segment(
+        &self,
+        index: usize,
+        state: &Mutex<Vec<Segment>>,
+        state_file: &Path,
+        written: &AtomicU64,
+        bar: &DownloadHandle,
+    ) -> Result<(), Error> {
+        let mut backoff = self.retry.backoff();
+        loop {
+            let segment: Segment = state.lock().unwrap()[index];
+            if segment.remaining() == 0 {
+                return Ok(());
+            }
+            let range = format!("bytes={}-{}", segment.start + segment.written, segment.end);
+            let resp = match self.session.get(self.url).header(RANGE, range).send().await {
+                Ok(resp) => {
+                    self.session.store_cookies(&resp);
+                    resp
+                }
+                Err(e) => {
+                    backoff.wait(e, None).await?;
+                    continue;
+                }
+            };
+            let status = resp.status();
+            if status != StatusCode::PARTIAL_CONTENT {
+                if status.is_client_error() && !retry::is_throttled(status) {
+                    return Err(Error::Status(status, self.url.to_string()));
+                }
+                let retry_after = match retry::is_throttled(status) {
+                    true => retry::retry_after(resp.headers()),
+                    false => None,
+                };
+                backoff.wait(status, retry_after).await?;
+                continue;
+            }
+            let mut file: File = OpenOptions::new()
+                .write(true)
+                .open(&self.part_file)
+                .await
+                .map_err(|e| Error::file(&self.part_file, e))?;
+            file.seek(SeekFrom::Start(segment.start + segment.written))
+                .await
+                .map_err(|e| Error::file(&self.part_file, e))?;
+            let mut buf: BytesMut = BytesMut::with_capacity(BLOCKSIZE);
+            let mut reader = StreamReader::new(resp.bytes_stream().map_err(std::io::Error::other));
+            let mut unsaved: usize = 0;
+            loop {
+                match reader.read_buf(&mut buf).await {
+                    Ok(0) => {
+                        backoff
+                            .wait("connection closed before completion", None)
+                            .await?;
+                        break;
                     }
-                    let content_length: usize = match resp.content_length() {
-                        Some(s) => s as usize,
-                        None => panic!("{resp:?}\n{}", self.url), /*{
-                                                                      // konachan does not return content_length header
-                                                                      // when bytes == content_length
-                                                                      if self.target_size > 0 {
-                                                                          is_success = true;
-                                                                          break 'downloader;
-                                                                      } else {
-                                                                          panic!(
-                                                                              "target_size: {ts}, target_file: {tf}, url: {url}",
-                                                                              ts = self.target_size,
-                                                                              tf = self.target_file.display(),
-                                                                              url = self.url,
-                                                                          );
-                                                                      }
-                                                                  }*/
-                    };
-                    //self.total_size = content_length + self.target_size;
-                    let file: File = match OpenOptions::new()
-                        .append(true)
-                        .create(true)
-                        .open(&self.target_file)
-                        .await
-                    {
-                        Ok(f) => f,
-                        Err(e) => panic!(
-                            "an error occured while opening file: {}\nerror: {:?}",
-                            self.target_file.display(),
-                            e
-                        ),
-                    };
-                    let mut writer = BufWriter::new(file);
-                    writer.seek(SeekFrom::End(0)).await.unwrap();
-                    let mut buf: BytesMut = BytesMut::with_capacity(BLOCKSIZE);
-                    let stream = resp.bytes_stream();
-                    let mut reader =
-                        StreamReader::new(stream.map_err(|e| std::io::Error::other(e)));
-                    println!(
-                        "content_length: {content_length}, target_size: {}\n{}",
-                        self.target_size,
-                        self.target_file.display(),
-                    );
-                    loop {
-                        match reader.read_buf(&mut buf).await {
-                            Ok(r) => {
-                                if r == 0 {
-                                    if self.written == content_length {
-                                        is_success = true;
-                                        break 'downloader;
-                                        /*println!(
-                                            //"\x1b[A\r\x1b[2K{GREEN}{}{RESET}",
-                                            "{GREEN}{}{RESET}",
-                                            target_file.display()
-                                        );
-                                        return Some(target_file);*/
-                                    } else {
-                                        if on_download_failure().is_err() {
-                                            return None;
-                                        }
-                                        sleep(self.retry_sleep).await;
-                                        continue 'downloader;
-                                    }
-                                }
-                                self.target_size += writer.write(&mut buf).await.unwrap();
-                                writer.flush().await.unwrap();
-                                buf.clear();
-                            }
-                            Err(_) => {
-                                if on_download_failure().is_err() {
-                                    return None;
-                                }
-                                sleep(self.retry_sleep).await;
-                                continue 'downloader;
-                            }
+                    Ok(_) => {
+                        // never write into the next segment, even if the server sends more
+                        let remaining = state.lock().unwrap()[index].remaining();
+                        let n = buf.len().min(remaining as usize);
+                        file.write_all(&buf[..n])
+                            .await
+                            .map_err(|e| Error::file(&self.part_file, e))?;
+                        buf.clear();
+                        state.lock().unwrap()[index].written += n as u64;
+                        bar.set(written.fetch_add(n as u64, Ordering::Relaxed) + n as u64);
+                        unsaved += n;
+                        let is_done = n as u64 == remaining;
+                        if unsaved >= BLOCKSIZE || is_done {
+                            // the data has to be on disk before the state says so
+                            file.flush()
+                                .await
+                                .map_err(|e| Error::file(&self.part_file, e))?;
+                            write_segments(state_file, &state.lock().unwrap())?;
+                            unsaved = 0;
                         }
+                        if is_done {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => {
+                        backoff.wait(e, None).await?;
+                        break;
                     }
                 }
-                Err(_) => {
-                    if on_download_failure().is_err() {
-                        return None;
+            }
+            file.flush()
+                .await
+                .map_err(|e| Error::file(&self.part_file, e))?;
+            write_segments(state_file, &state.lock().unwrap())?;
+        }
+    }
+
+    /// checks the file against the expected size and md5, when they're known.
+    async fn verify(&self, file: &Path) -> bool {
+        if let Some(size) = self.expected_size.filter(|size| *size > 0) {
+            match metadata(file).await {
+                Ok(m) if m.st_size() as usize == size => (),
+                _ => return false,
+            }
+        }
+        match self.md5 {
+            Some(md5) => match utils::md5sum(file).await {
+                Ok(sum) => sum.eq_ignore_ascii_case(md5),
+                Err(e) => {
+                    PROGRESS.eprintln(format!("unable to read {}: {e:?}", file.display()));
+                    false
+                }
+            },
+            None => true,
+        }
+    }
+
+    async fn truncate(&mut self) -> Result<(), Error> {
+        match OpenOptions::new()
+            .write(true)
+            .truncate(true)
+            .open(&self.part_file)
+            .await
+        {
+            Ok(_) => self.target_size = 0,
+            Err(e) => return Err(Error::file(&self.part_file, e)),
+        }
+        return Ok(());
+    }
+}
+
+/// total size from `Content-Range: bytes <start>-<end>/<total>`
+fn content_range_total(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(CONTENT_RANGE)?
+        .to_str()
+        .ok()?
+        .rsplit_once('/')?
+        .1
+        .parse::<usize>()
+        .ok()
+}
+
+/// byte range `start..=end` of a segmented download, `written` bytes of it are done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Segment {
+    start: u64,
+    end: u64,
+    written: u64,
+}
+
+impl Segment {
+    fn split(total: u64, count: usize) -> Vec<Self> {
+        let count = (count as u64).clamp(1, total);
+        let chunk = total.div_ceil(count);
+        (0..count)
+            .map(|i| Segment {
+                start: i * chunk,
+                end: ((i + 1) * chunk).min(total) - 1,
+                written: 0,
+            })
+            .filter(|s| s.start <= s.end)
+            .collect()
+    }
+
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    fn remaining(&self) -> u64 {
+        self.len().saturating_sub(self.written)
+    }
+}
+
+/// the segments of a previous run, None when the state file is missing, corrupt or doesn't
+/// belong to `part_file` of `expected` bytes
+async fn read_segments(
+    path: &Path,
+    part_file: &Path,
+    expected: Option<u64>,
+) -> Option<Vec<Segment>> {
+    let segments = parse_segments(fs::read_to_string(path).await.ok()?.as_str())?;
+    let total = segments.last()?.end + 1;
+    let part_size = fs::metadata(part_file).await.ok()?.len();
+    match part_size == total && expected.is_none_or(|e| e == total) {
+        true => return Some(segments),
+        false => return None,
+    }
+}
+
+/// one `start end written` line per segment, they have to cover the file from its start
+/// without gaps or overlaps
+fn parse_segments(content: &str) -> Option<Vec<Segment>> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut next: u64 = 0;
+    for line in content.lines() {
+        let fields = line
+            .split_whitespace()
+            .map(|f| f.parse::<u64>().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        match fields[..] {
+            [start, end, written]
+                if start == next && start <= end && written <= end - start + 1 =>
+            {
+                segments.push(Segment {
+                    start,
+                    end,
+                    written,
+                });
+                next = end.checked_add(1)?;
+            }
+            _ => return None,
+        }
+    }
+    match segments.is_empty() {
+        true => None,
+        false => Some(segments),
+    }
+}
+
+fn write_segments(path: &Path, segments: &[Segment]) -> Result<(), Error> {
+    std::fs::write(path, format_segments(segments)).map_err(|e| Error::file(path, e))
+}
+
+fn format_segments(segments: &[Segment]) -> String {
+    segments
+        .iter()
+        .map(|s| format!("{} {} {}\n", s.start, s.end, s.written))
+        .collect::<String>()
+}
+
+/// `<file>.part.segments`
+fn segments_path(part_file: &Path) -> PathBuf {
+    let mut path: OsString = part_file.as_os_str().to_owned();
+    path.push(".");
+    path.push(SEGMENTS_EXT);
+    return PathBuf::from(path);
+}
+
+/// `<file>.part`
+pub fn part_path(file: &Path) -> PathBuf {
+    let mut part: OsString = file.as_os_str().to_owned();
+    part.push(".");
+    part.push(PART_EXT);
+    return PathBuf::from(part);
+}
+
+/// removes leftover part files of downloads into `template`, either their target already
+/// exists or they haven't been touched for `PART_MAX_AGE`.
+/// files whose name doesn't fit the template are left alone, they weren't written by us.
+pub async fn clean_part_files<P: AsRef<Path>>(template: P) {
+    let template = template.as_ref();
+    let root = utils::template_root(template.to_string_lossy().as_ref());
+    let mut dirs: Vec<PathBuf> = Vec::from([root]);
+    while let Some(dir) = dirs.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(e) => e,
+            Err(_) => continue,
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            let meta = match entry.metadata().await {
+                Ok(m) => m,
+                Err(_) => continue,
+            };
+            if meta.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            // state of a segmented download without its part file
+            if path.extension().is_some_and(|ext| ext == SEGMENTS_EXT) {
+                let part = path.with_extension("");
+                if part.extension().is_some_and(|ext| ext == PART_EXT)
+                    && utils::matches_template(template, &part.with_extension(""))
+                    && metadata(&part).await.is_err()
+                {
+                    fs::remove_file(&path).await.ok();
+                }
+                continue;
+            }
+            if path.extension().is_none_or(|ext| ext != PART_EXT)
+                || !utils::matches_template(template, &path.with_extension(""))
+            {
+                continue;
+            }
+            let is_stale = meta
+                .modified()
+                .ok()
+                .and_then(|m| SystemTime::now().duration_since(m).ok())
+                .is_some_and(|age| age > PART_MAX_AGE);
+            if is_stale || metadata(path.with_extension("")).await.is_ok() {
+                fs::remove_file(segments_path(&path)).await.ok();
+                match fs::remove_file(&path).await {
+                    Ok(_) => {
+                        PROGRESS.println(format!("removed orphaned part file: {}", path.display()))
+                    }
+                    Err(e) => {
+                        PROGRESS.eprintln(format!("unable to remove {}: {e:?}", path.display()))
                     }
-                    sleep(self.retry_sleep).await;
-                    continue 'downloader;
                 }
             }
         }
-        /*status = Status::Done;
-        handle.await.unwrap();*/
-        if is_success {
-            println!("{GREEN}{}{RESET}", self.target_file.display());
-            return Some(self.target_file);
-        } else {
-            return None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("booruchan-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn segments_cover_the_file() {
+        for (total, count) in [(1, 1), (1, 8), (10, 3), (100, 4), (101, 4), (7, 7), (5, 0)] {
+            let segments = Segment::split(total, count);
+            assert!(!segments.is_empty() && segments.len() <= count.max(1));
+            assert_eq!(segments[0].start, 0);
+            assert_eq!(segments.last().unwrap().end, total - 1);
+            for pair in segments.windows(2) {
+                assert_eq!(pair[0].end + 1, pair[1].start, "{total} bytes in {count}");
+            }
+            assert_eq!(segments.iter().map(|s| s.len()).sum::<u64>(), total);
+            assert!(segments
+                .iter()
+                .all(|s| s.written == 0 && s.remaining() == s.len()));
+        }
+    }
+
+    #[test]
+    fn segments_state_round_trip() {
+        let mut segments = Segment::split(1000, 3);
+        segments[0].written = segments[0].len();
+        segments[1].written = 12;
+        assert_eq!(
+            parse_segments(&format_segments(&segments)),
+            Some(segments.clone())
+        );
+
+        let path = temp_path("round-trip.segments");
+        write_segments(&path, &segments).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(parse_segments(&content), Some(segments));
+    }
+
+    #[test]
+    fn corrupt_segments_state() {
+        for content in [
+            "",
+            "0 9",
+            "0 9 0 0",
+            "a b c",
+            "0 9 -1",
+            // more written than the segment holds
+            "0 9 11",
+            "9 0 0",
+            // doesn't start at the beginning of the file
+            "1 9 0",
+            // gap
+            "0 4 0\n6 9 0",
+            // overlap
+            "0 5 0\n5 9 0",
+        ] {
+            assert_eq!(parse_segments(content), None, "{content:?}");
         }
     }
+
+    #[tokio::test]
+    async fn mismatched_segments_state() {
+        let (state, part) = (temp_path("mismatch.segments"), temp_path("mismatch.part"));
+        write_segments(&state, &Segment::split(100, 2)).unwrap();
+        std::fs::write(&part, [0u8; 100]).unwrap();
+        assert!(read_segments(&state, &part, None).await.is_some());
+        assert!(read_segments(&state, &part, Some(100)).await.is_some());
+        // the post changed since the part file was written
+        assert!(read_segments(&state, &part, Some(120)).await.is_none());
+        std::fs::write(&part, [0u8; 50]).unwrap();
+        assert!(read_segments(&state, &part, None).await.is_none());
+        std::fs::remove_file(&part).ok();
+        assert!(read_segments(&state, &part, None).await.is_none());
+        std::fs::remove_file(&state).ok();
+    }
+
+    #[test]
+    fn content_range_totals() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_RANGE, HeaderValue::from_str(value).unwrap());
+            return headers;
+        };
+        assert_eq!(content_range_total(&headers("bytes 0-0/1234")), Some(1234));
+        assert_eq!(
+            content_range_total(&headers("bytes 200-1000/67589")),
+            Some(67589)
+        );
+        // the total isn't known
+        assert_eq!(content_range_total(&headers("bytes 0-0/*")), None);
+        assert_eq!(content_range_total(&headers("bytes */1234")), Some(1234));
+        assert_eq!(content_range_total(&headers("bytes 0-0")), None);
+        assert_eq!(content_range_total(&HeaderMap::new()), None);
+    }
 }
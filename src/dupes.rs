@@ -0,0 +1,142 @@
+//! near duplicate images. every saved image gets a difference hash (dHash): the image is
+//! shrunk to 9x8 grayscale pixels and each bit tells whether a pixel is brighter than its
+//! right neighbour. re-encoded or resized copies end up a few bits apart while their md5 differs.
+
+use std::path::Path;
+
+// crate
+use image::{imageops::FilterType, ImageFormat, ImageReader};
+use sqlite::State;
+
+// local
+use crate::{
+    schema,
+    search::{clouds, SearchResult},
+    worker::{read_entry, DbEntry, COLUMNS},
+    Config, Error,
+};
+
+/// whether `dhash` can decode files with this extension
+pub fn is_image(ext: &str) -> bool {
+    ImageFormat::from_extension(ext).is_some_and(|f| f.reading_enabled())
+}
+
+/// difference hash of an image, stored as i64 since sqlite has no unsigned integers
+pub fn dhash<P: AsRef<Path>>(path: P) -> Result<i64, Error> {
+    let path = path.as_ref();
+    let image = ImageReader::open(path)
+        .map_err(|e| Error::file(path, e))?
+        .with_guessed_format()
+        .map_err(|e| Error::file(path, e))?
+        .decode()?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+    let mut hash: u64 = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if image.get_pixel(x, y)[0] < image.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    return Ok(hash as i64);
+}
+
+/// number of differing bits between two hashes
+pub fn distance(a: i64, b: i64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// groups images whose hashes are at most `distance` apart, directly or through other images
+/// of the group. groups are sorted by resolution, highest first.
+/// every pair of hashes is compared, so it's quadratic in the number of hashed images.
+fn clusters(entries: Vec<(String, DbEntry)>, distance: u32) -> Vec<Vec<(String, DbEntry)>> {
+    let hashes: Vec<i64> = entries
+        .iter()
+        .map(|(_, e)| e.dhash.unwrap_or_default())
+        .collect();
+    // union find
+    let mut parent: Vec<usize> = (0..entries.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        return i;
+    }
+    for i in 0..hashes.len() {
+        for j in (i + 1)..hashes.len() {
+            if self::distance(hashes[i], hashes[j]) <= distance {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a] = b;
+            }
+        }
+    }
+    let mut groups: std::collections::BTreeMap<usize, Vec<(String, DbEntry)>> =
+        std::collections::BTreeMap::new();
+    for (i, entry) in entries.into_iter().enumerate() {
+        groups.entry(root(&mut parent, i)).or_default().push(entry);
+    }
+    let mut ret: Vec<Vec<(String, DbEntry)>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    for group in ret.iter_mut() {
+        group.sort_by_key(|(_, e)| std::cmp::Reverse(e.resolution()));
+    }
+    return ret;
+}
+
+/// the `dupes` command, prints the groups of near duplicates separated by empty lines,
+/// or a json array of groups with `json`
+pub fn run(config: &Config, distance: Option<u32>, json: bool) -> Result<(), Error> {
+    let (connection, tables) = schema::open(config)?;
+    let union = tables
+        .iter()
+        .map(|(schema, table)| {
+            format!(
+                "SELECT {COLUMNS}, '{table}' AS platform FROM {schema}.{table}
+                    WHERE dhash IS NOT NULL"
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(" UNION ALL ");
+    let mut statement = connection.prepare(union)?;
+    let mut entries: Vec<(String, DbEntry)> = Vec::new();
+    while let State::Row = statement.next()? {
+        let platform = statement.read::<String, _>("platform")?;
+        entries.push((platform, read_entry(&statement)?));
+    }
+    let clouds = clouds(config);
+    let groups: Vec<Vec<SearchResult>> =
+        clusters(entries, distance.unwrap_or(config.global.near_distance))
+            .into_iter()
+            .map(|group| {
+                group
+                    .into_iter()
+                    .map(|(platform, entry)| SearchResult::new(&clouds, platform, entry))
+                    .collect()
+            })
+            .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&groups)?);
+        return Ok(());
+    }
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        for result in group {
+            println!(
+                "{platform} {id} {width}x{height} {location}",
+                platform = result.platform,
+                id = result.entry.id,
+                width = result.entry.width.unwrap_or(0),
+                height = result.entry.height.unwrap_or(0),
+                location = result.location,
+            );
+        }
+    }
+    return Ok(());
+}
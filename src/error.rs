@@ -0,0 +1,136 @@
+use std::{fmt, io, path::PathBuf};
+
+// crate
+use reqwest::StatusCode;
+use tokio::sync::{mpsc, oneshot};
+
+// local
+use crate::{progress::human_bytes, retry::RetryError};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// io error on a specific file or directory
+    File(PathBuf, io::Error),
+    Http(reqwest::Error),
+    /// response status we can't recover from, e.g. 404
+    Status(StatusCode, String),
+    Retry(RetryError),
+    Json(serde_json::Error),
+    Database(sqlite::Error),
+    /// the database schema can't be migrated
+    Schema(String),
+    Image(image::ImageError),
+    /// invalid filename or directory template
+    Format(String),
+    /// unexpected data from the api
+    Api(String),
+    /// invalid search query
+    Query(String),
+    /// rclone failed to copy or move a file
+    Rclone(String),
+    /// the http client of a platform can't be set up, e.g. an invalid proxy url
+    Client(String),
+    /// not enough free space left for a download while `low_space` is `skip`
+    DiskSpace {
+        path: PathBuf,
+        available: u64,
+        required: u64,
+    },
+    /// the byte budget of the run or platform is used up
+    Budget,
+    /// the worker is gone, nothing can be saved anymore
+    Channel,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {e}"),
+            Error::File(path, e) => write!(f, "{}: {e}", path.display()),
+            Error::Http(e) => write!(f, "http error: {e}"),
+            Error::Status(status, url) => write!(f, "{url}: {status}"),
+            Error::Retry(e) => write!(f, "{e}"),
+            Error::Json(e) => write!(f, "json error: {e}"),
+            Error::Database(e) => write!(f, "database error: {e}"),
+            Error::Schema(s) => write!(f, "schema error: {s}"),
+            Error::Image(e) => write!(f, "image error: {e}"),
+            Error::Format(s) => write!(f, "format error: {s}"),
+            Error::Api(s) => write!(f, "api error: {s}"),
+            Error::Query(s) => write!(f, "invalid query: {s}"),
+            Error::Rclone(s) => write!(f, "rclone: {s}"),
+            Error::Client(s) => write!(f, "client error: {s}"),
+            Error::DiskSpace {
+                path,
+                available,
+                required,
+            } => write!(
+                f,
+                "not enough space on {}: {} available, {} required",
+                path.display(),
+                human_bytes(*available as f64),
+                human_bytes(*required as f64)
+            ),
+            Error::Budget => write!(f, "byte budget is used up"),
+            Error::Channel => write!(f, "worker is not running"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn file<P: Into<PathBuf>>(path: P, e: io::Error) -> Self {
+        Error::File(path.into(), e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Error::Io(value)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(value: reqwest::Error) -> Self {
+        Error::Http(value)
+    }
+}
+
+impl From<RetryError> for Error {
+    fn from(value: RetryError) -> Self {
+        Error::Retry(value)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(value: serde_json::Error) -> Self {
+        Error::Json(value)
+    }
+}
+
+impl From<sqlite::Error> for Error {
+    fn from(value: sqlite::Error) -> Self {
+        Error::Database(value)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(value: image::ImageError) -> Self {
+        Error::Image(value)
+    }
+}
+
+impl<T> From<mpsc::error::SendError<T>> for Error {
+    fn from(_: mpsc::error::SendError<T>) -> Self {
+        Error::Channel
+    }
+}
+
+impl From<oneshot::error::RecvError> for Error {
+    fn from(_: oneshot::error::RecvError) -> Self {
+        Error::Channel
+    }
+}
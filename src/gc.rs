@@ -0,0 +1,161 @@
+//! the `gc` command, finds files under the configured directories that no post points to
+//! and posts whose file is gone. nothing is changed without `apply`.
+//! platforms that upload to the cloud are left out, their files aren't kept locally.
+
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+// crate
+use sqlite::{Connection, State};
+use tokio::fs;
+
+// local
+use crate::{
+    schema,
+    utils::{self, matches_template, normalize, template_root},
+    worker::{prune_entry, read_entry, requeue_entry, DbEntry, COLUMNS},
+    Config, Error,
+};
+
+fn exists(path: &str) -> Result<bool, Error> {
+    match std::fs::symlink_metadata(path) {
+        Ok(_) => return Ok(true),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(Error::file(path, e)),
+    }
+}
+
+/// removes the directories left empty by removing `file`, up to `root`
+async fn remove_empty_dirs(file: &Path, root: &Path) {
+    for dir in file.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// the `gc` command. with `apply` orphan files are removed and posts whose file is gone
+/// are pruned, or kept and recorded as failed with `requeue` so `retry-failed` downloads
+/// them again.
+pub async fn run(config: &Config, apply: bool, requeue: bool) -> Result<(), Error> {
+    let local: Vec<(&'static str, Vec<PathBuf>)> = config
+        .platforms
+        .iter()
+        .filter(|p| !p.config().to_cloud)
+        .map(|p| {
+            let c = p.config();
+            let mut templates = vec![format!("{}/{}", c.target_dir, c.filename)];
+            if let Some(ref compress) = c.compress {
+                templates.push(format!("{}/{}", compress.target_dir, compress.filename));
+            }
+            let templates = templates
+                .iter()
+                .map(|t| normalize(t.replace("{platform}", p.name()).as_str()))
+                .collect();
+            (p.name(), templates)
+        })
+        .collect();
+
+    // every file a post points to, including the posts of other platforms and databases.
+    // posts without their file are kept with the index of their database.
+    let mut connections: Vec<Connection> = Vec::new();
+    let mut known: HashSet<PathBuf> = HashSet::new();
+    let mut gone: Vec<(usize, &'static str, DbEntry)> = Vec::new();
+    let mut gone_compressed: Vec<(usize, &'static str, DbEntry)> = Vec::new();
+    for (database, tables) in config.databases() {
+        let connection = Connection::open(&database)?;
+        schema::migrate(&connection)?;
+        for platform in tables {
+            let is_local = local.iter().any(|(name, _)| *name == platform);
+            let mut statement = connection.prepare(format!("SELECT {COLUMNS} FROM {platform}"))?;
+            while let State::Row = statement.next()? {
+                let entry = read_entry(&statement)?;
+                known.insert(normalize(entry.path.as_str()));
+                if let Some(ref compress_path) = entry.compress_path {
+                    known.insert(normalize(compress_path.as_str()));
+                }
+                if !is_local {
+                    continue;
+                }
+                if !exists(entry.path.as_str())? {
+                    gone.push((connections.len(), platform, entry));
+                } else if let Some(ref compress_path) = entry.compress_path {
+                    if !exists(compress_path.as_str())? {
+                        gone_compressed.push((connections.len(), platform, entry));
+                    }
+                }
+            }
+        }
+        connections.push(connection);
+    }
+
+    let templates: Vec<&PathBuf> = local.iter().flat_map(|(_, t)| t).collect();
+    let mut roots: Vec<PathBuf> = templates
+        .iter()
+        .map(|t| template_root(t.to_string_lossy().as_ref()))
+        .collect();
+    roots.sort();
+    // nested roots are listed with their parent
+    roots.dedup_by(|root, parent| root.starts_with(parent));
+    let orphans: Vec<PathBuf> = utils::list_files(&roots)
+        .await
+        .into_iter()
+        .filter(|f| !utils::is_partial(f) && !known.contains(f))
+        .filter(|f| templates.iter().any(|t| matches_template(t, f)))
+        .collect();
+
+    for file in orphans.iter() {
+        println!("orphan file: {}", file.display());
+        if !apply {
+            continue;
+        }
+        match fs::remove_file(file).await {
+            Ok(_) => {
+                if let Some(root) = roots.iter().find(|r| file.starts_with(r)) {
+                    remove_empty_dirs(file, root).await;
+                }
+            }
+            Err(e) => eprintln!("{}: {e}", file.display()),
+        }
+    }
+    for (database, platform, entry) in gone.iter() {
+        println!("{platform} {}: file is gone: {}", entry.id, entry.path);
+        let connection = &connections[*database];
+        match (apply, requeue) {
+            // the post is kept, its metadata would be lost if the download doesn't work out
+            (true, true) => {
+                requeue_entry(connection, platform, entry, "gc: file is gone".to_string())?
+            }
+            (true, false) => prune_entry(connection, platform, entry)?,
+            (false, _) => (),
+        }
+    }
+    for (database, platform, entry) in gone_compressed.iter() {
+        println!(
+            "{platform} {}: compressed file is gone: {}",
+            entry.id,
+            entry.compress_path.as_deref().unwrap_or_default()
+        );
+        if apply {
+            let mut statement = connections[*database].prepare(format!(
+                "UPDATE {platform} SET compress_path = NULL WHERE id = ?"
+            ))?;
+            statement.bind((1, entry.id))?;
+            while let State::Row = statement.next()? {}
+        }
+    }
+    let found = format!(
+        "{} orphan files, {} posts without their file, {} posts without their compressed file",
+        orphans.len(),
+        gone.len(),
+        gone_compressed.len()
+    );
+    match apply {
+        true => eprintln!("removed {found}"),
+        false => eprintln!("found {found}, run with --apply to remove them"),
+    }
+    return Ok(());
+}
@@ -0,0 +1,116 @@
+//! the `import` command, files saved by other tools are matched to their posts and recorded
+//! in the database so the next runs skip them. files are found by md5 or by names like
+//! `yande.re 12345 tag1 tag2.jpg`, `Konachan.com - 12345 tag1.png`.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{LazyLock, Mutex},
+};
+
+// crate
+use tokio::{fs, sync::OnceCell};
+
+// local
+use crate::{
+    platforms::statics::{KONACHAN, SAKUGABOORU, YANDERE},
+    statics::{ARGS, PROGRESS},
+    utils,
+};
+
+#[derive(Debug, Clone)]
+pub struct ImportFile {
+    pub path: PathBuf,
+    pub md5: String,
+    pub file_size: u64,
+    pub ext: Option<String>,
+    /// platform, id and tags found in the filename
+    pub platform: Option<&'static str>,
+    pub id: Option<i64>,
+    pub tags: Option<String>,
+}
+
+static FILES: OnceCell<Vec<ImportFile>> = OnceCell::const_new();
+static CLAIMED: LazyLock<Mutex<HashSet<PathBuf>>> = LazyLock::new(Default::default);
+
+/// the files in the directories of the command line, scanned once for all platforms
+pub async fn files() -> &'static [ImportFile] {
+    FILES.get_or_init(|| scan(&ARGS.paths)).await
+}
+
+/// claims a file for a platform, false if another platform already imported it
+pub fn claim(path: &Path) -> bool {
+    CLAIMED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(path.to_path_buf())
+}
+
+async fn scan(dirs: &[PathBuf]) -> Vec<ImportFile> {
+    let mut ret: Vec<ImportFile> = Vec::new();
+    for path in utils::list_files(dirs).await {
+        if utils::is_partial(&path) {
+            continue;
+        }
+        let metadata = match fs::symlink_metadata(&path).await {
+            Ok(m) if m.is_file() => m,
+            Ok(_) => continue,
+            Err(e) => {
+                PROGRESS.eprintln(format!("{}: {e}", path.display()));
+                continue;
+            }
+        };
+        PROGRESS.println(format!("import: hashing {}", path.display()));
+        let md5 = match utils::md5sum(&path).await {
+            Ok(md5) => md5,
+            Err(e) => {
+                PROGRESS.eprintln(format!("{}: {e}", path.display()));
+                continue;
+            }
+        };
+        let mut file = ImportFile {
+            ext: path
+                .extension()
+                .map(|e| e.to_string_lossy().to_ascii_lowercase()),
+            path,
+            md5,
+            file_size: metadata.len(),
+            platform: None,
+            id: None,
+            tags: None,
+        };
+        parse_name(&mut file);
+        ret.push(file);
+    }
+    return ret;
+}
+
+fn platform(name: &str) -> Option<&'static str> {
+    match name.to_ascii_lowercase().as_str() {
+        "yande.re" | "yandere" => Some(YANDERE),
+        "konachan.com" | "konachan.net" | "konachan" => Some(KONACHAN),
+        "sakugabooru.com" | "sakugabooru" => Some(SAKUGABOORU),
+        _ => None,
+    }
+}
+
+/// `<platform> [-] <id> [tags]`, other files are only matched by md5
+fn parse_name(file: &mut ImportFile) {
+    let stem = match file.path.file_stem() {
+        Some(stem) => stem.to_string_lossy().to_string(),
+        None => return,
+    };
+    let mut words = stem.split_whitespace().filter(|w| *w != "-");
+    let platform = match words.next().and_then(platform) {
+        Some(p) => p,
+        None => return,
+    };
+    let id = match words.next().map(|w| w.parse::<i64>()) {
+        Some(Ok(id)) => id,
+        _ => return,
+    };
+    let tags = words.collect::<Vec<&str>>().join(" ");
+    file.platform = Some(platform);
+    file.id = Some(id);
+    file.tags = (!tags.is_empty()).then_some(tags);
+}
@@ -1,14 +1,29 @@
+#![allow(clippy::needless_return, clippy::single_match)]
+
 mod args;
+pub mod client;
 mod downloader;
-pub use downloader::Downloader;
+pub mod dupes;
+mod error;
+pub mod gc;
+pub use downloader::{clean_part_files, Downloader};
+pub use error::{Error, Result};
 mod config;
+pub mod cookies;
+pub mod import;
 pub mod platforms;
+pub mod progress;
+pub mod quota;
 pub mod rclone;
-pub use config::{Config, PlatformConfig};
+pub mod retry;
+mod schema;
+pub mod search;
+pub use config::{Config, PlatformConfig, Proxy};
 //pub use platforms::base::init_platforms;
 pub mod utils;
+pub mod verify;
 pub mod worker;
-pub use args::Args;
+pub use args::{Args, Command};
 
 #[macro_export]
 macro_rules! pub_struct {
@@ -23,6 +38,15 @@ macro_rules! pub_struct {
 pub mod consts {
     pub const NULL: &str = "null";
     pub const BLOCKSIZE: usize = 1048576;
+    pub const PART_EXT: &str = "part";
+    pub const SEGMENTS_EXT: &str = "segments";
+    pub const PART_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+    /// how long to wait before checking free space again when `low_space` is `pause`
+    pub const LOW_SPACE_WAIT: std::time::Duration = std::time::Duration::from_secs(60);
+    /// how often idle download workers check the queue while the crawler is running
+    pub const QUEUE_POLL: std::time::Duration = std::time::Duration::from_secs(5);
+    /// milliseconds a query waits for a database locked by another worker
+    pub const BUSY_TIMEOUT: usize = 5000;
     pub const GREEN: &str = "\x1b[32;1;1m";
     pub const RESET: &str = "\x1b[0m";
 
@@ -40,11 +64,16 @@ pub mod consts {
 
 pub mod statics {
     use std::env::var;
-    use std::sync::LazyLock;
+    use std::sync::{LazyLock, Mutex};
 
-    use crate::Args;
+    use crate::{progress::Progress, quota::Quota, Args};
 
-    pub static ARGS: LazyLock<Args> = LazyLock::new(|| Args::parse());
+    pub static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
+    pub static PROGRESS: LazyLock<Progress> = LazyLock::new(Progress::new);
+    /// byte budget of the whole run, shared by all platforms
+    pub static QUOTA: LazyLock<Quota> = LazyLock::new(Quota::default);
+    /// bytes of downloads in progress, the free space doesn't count them yet
+    pub static RESERVED_SPACE: Mutex<u64> = Mutex::new(0);
     //pub static CONFIG: LazyLock<Config> = LazyLock::new(|| Config::load());
     pub static HOME: LazyLock<String> = LazyLock::new(|| match var("HOME") {
         Ok(v) => v.trim_end_matches('/').to_string(),
@@ -56,7 +85,7 @@ pub mod statics {
 }
 
 pub(crate) mod fmt {
-    use crate::consts::NULL;
+    use crate::{consts::NULL, Error, Result};
 
     const ARR: [char; 2] = ['[', ']'];
     const VALUE_SEP: char = ',';
@@ -96,7 +125,7 @@ pub(crate) mod fmt {
         pub style: Vec<&'k str>,
     }
     impl<'k> Keywords<'k> {
-        fn parse_key(&self, substr: &'k str) -> Key {
+        fn parse_key(&self, substr: &'k str) -> Result<Key<'k>> {
             let mut key = Key {
                 substr,
                 index: Vec::new(),
@@ -108,12 +137,18 @@ pub(crate) mod fmt {
                 .collect::<Vec<&str>>();
             let split_len = split.len();
             if split_len == 1 && split[0] == substr {
-                return key;
+                return Ok(key);
             } else if split_len == 2 {
                 key.substr = split[1];
-            } else if split_len > 2 {
-                panic!("error while parsing {}", key.substr);
+            } else {
+                return Err(Error::Format(format!("unable to parse key: {substr}")));
             }
+            let parse_index = |index: &str| -> Result<i64> {
+                index
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| Error::Format(format!("invalid index '{index}' in key: {substr}")))
+            };
             /*
                 split index values by splitting them with ','
                 and split ranges with ':'
@@ -129,48 +164,32 @@ pub(crate) mod fmt {
                             key.index.push(Index::Range(0, -1));
                             continue;
                         }
-                        // not sure if this is supposed to return 1 or 2 items
-                        let end = index.rsplitn(2, RANGE_SEP).collect::<Vec<&str>>();
-                        if end.len() == 1 {
-                            key.index
-                                .push(Index::Range(0, end[0].parse::<i64>().unwrap()));
-                        } else {
-                            // I don't know what error we'll have yet
-                            panic!("end: {end:?}");
-                        }
+                        // case: [:i]
+                        let end = parse_index(&index[1..])?;
+                        key.index.push(Index::Range(0, end));
                     } else if index.ends_with(RANGE_SEP) {
                         // case: [i:]
                         // we'll consider it as [i:-1]
-                        let start = index.rsplitn(1, RANGE_SEP).collect::<Vec<&str>>();
-                        if start.len() == 1 {
-                            key.index
-                                .push(Index::Range(start[0].parse::<i64>().unwrap(), -1));
-                        } else {
-                            panic!(
-                                "error while parsing: start: {start:?}, key: {:?}",
-                                key.substr
-                            );
-                        }
+                        let start = parse_index(&index[..index.len() - 1])?;
+                        key.index.push(Index::Range(start, -1));
                     } else {
                         // this is a proper case of slicing
                         // [start:end]
-                        let start_end = index.splitn(2, RANGE_SEP).collect::<Vec<&str>>();
-                        let start = start_end[0].parse::<i64>().unwrap();
-                        let end = start_end[1].parse::<i64>().unwrap();
+                        let (start, end) = index.split_once(RANGE_SEP).unwrap();
+                        let (start, end) = (parse_index(start)?, parse_index(end)?);
                         if start > end {
-                            panic!(
+                            return Err(Error::Format(format!(
                                 "starting index can't be higher than end in ranges: {start}:{end}"
-                            );
+                            )));
                         }
                         key.index.push(Index::Range(start, end));
                     }
                 } else {
                     // single index, [index]
-                    let to_i64 = index.parse::<i64>().unwrap();
-                    key.index.push(Index::Select(to_i64));
+                    key.index.push(Index::Select(parse_index(index)?));
                 }
             }
-            return key;
+            return Ok(key);
         }
         fn get_indexes(&self, fmt_str: &str) -> Vec<(usize, usize)> {
             let mut in_brackets = false;
@@ -219,13 +238,13 @@ pub(crate) mod fmt {
                         }
                     } else if end == -1 {
                         let _start: usize = start as usize;
-                        let mut ret = String::with_capacity(str_len - _start);
+                        let mut ret = String::with_capacity(str_len.saturating_sub(_start));
                         for c in string.chars().skip(_start) {
                             ret.push(c);
                         }
                         return ret;
                     } else {
-                        let mut ret = String::with_capacity((end - start) as usize);
+                        let mut ret = String::with_capacity(end.saturating_sub(start) as usize);
                         for (i, c) in string.char_indices().skip(start as usize) {
                             if i as i64 == end {
                                 break;
@@ -288,7 +307,7 @@ pub(crate) mod fmt {
                 }
                 Index::Select(i) => {
                     if *i == -1 {
-                        match array.get(arr_len - 1) {
+                        match array.last() {
                             Some(s) => s.to_string(),
                             None => NULL.to_string(),
                         }
@@ -302,7 +321,7 @@ pub(crate) mod fmt {
             }
         }
 
-        pub fn format(&self, fmt_str: &'k str) -> String {
+        pub fn format(&self, fmt_str: &'k str) -> Result<String> {
             //println!("{}", fmt_str);
             let mut ret = String::from(fmt_str);
             let indexes = self.get_indexes(fmt_str);
@@ -313,7 +332,7 @@ pub(crate) mod fmt {
                 if !substr.contains(ARR) {
                     match self.get(substr) {
                         Some(v) => match v {
-                            Value::String(ref s) => {
+                            Value::String(s) => {
                                 ret = ret.replace(substr_all, s);
                             }
                             Value::Array(ref a) => {
@@ -329,13 +348,13 @@ pub(crate) mod fmt {
                                 ret = ret.replace(substr_all, _n.as_str());
                             }
                         },
-                        None => panic!("invalid key: {}", substr),
+                        None => return Err(Error::Format(format!("invalid key: {substr}"))),
                     }
                 } else {
-                    let mut key = self.parse_key(substr);
+                    let mut key = self.parse_key(substr)?;
                     let val = match self.get(key.substr) {
                         Some(v) => v,
-                        None => panic!("invalid key: {}", key.substr),
+                        None => return Err(Error::Format(format!("invalid key: {}", key.substr))),
                     };
                     if !key.index.is_empty() {
                         let mut repl = String::new();
@@ -359,12 +378,31 @@ pub(crate) mod fmt {
                     }
                 }
             }
-            return ret;
+            return Ok(ret);
+        }
+
+        /// every tag with its type, as stored in the `tags` table
+        pub fn typed_tags(&self) -> Vec<(String, Option<&'static str>)> {
+            use crate::consts::tag_types::*;
+
+            [
+                (&self.general, GENERAL),
+                (&self.character, CHARACTER),
+                (&self.copyright, COPYRIGHT),
+                (&self.artist, ARTIST),
+                (&self.metadata, METADATA),
+                (&self.circle, CIRCLE),
+                (&self.faults, FAULTS),
+                (&self.style, STYLE),
+            ]
+            .into_iter()
+            .flat_map(|(tags, _type)| tags.iter().map(move |t| (t.to_string(), Some(_type))))
+            .collect()
         }
 
         //async fn get_range(&self, field: &'k str, )
 
-        pub fn get(&self, field: &'k str) -> Option<Value> {
+        pub fn get(&self, field: &'k str) -> Option<Value<'_>> {
             match field {
                 "platform" => Some(Value::String(self.platform)),
                 "id" => Some(Value::Signed(self.id)),
@@ -1,5 +1,18 @@
+#![allow(clippy::needless_return)]
+
 // local imports
-use booruchan::{statics::ARGS, worker::Operation, worker::Worker, Config};
+use booruchan::{
+    clean_part_files, dupes, gc, search,
+    statics::{ARGS, PROGRESS, QUOTA},
+    utils::{normalize, recursive_dir_create_blocking},
+    verify,
+    worker::Operation,
+    worker::Worker,
+    Command, Config, Error,
+};
+
+// std
+use std::{path::PathBuf, process::exit};
 
 // crate
 use tokio::{
@@ -10,28 +23,98 @@ use tokio::{
 #[tokio::main]
 async fn main() {
     let conf = Config::load();
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .user_agent("booruchan/0.1.0")
-        .build()
-        .unwrap();
+    // commands that only read the database
+    let result = match ARGS.command {
+        Command::Search => Some(search::run(&conf, &ARGS.query, ARGS.json)),
+        Command::Dupes => Some(dupes::run(&conf, ARGS.distance, ARGS.json)),
+        Command::Verify => Some(verify::run(&conf, ARGS.requeue, ARGS.json).await),
+        Command::Gc => Some(gc::run(&conf, ARGS.apply, ARGS.requeue).await),
+        _ => None,
+    };
+    match result {
+        Some(Ok(_)) => return,
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+        None => (),
+    }
+    if ARGS.command == Command::Import && ARGS.paths.is_empty() {
+        eprintln!("import: no directories given");
+        exit(1);
+    }
+    QUOTA.set_limit(conf.global.budget);
+    for p in conf.platforms.iter() {
+        let c = p.config();
+        let template = format!("{}/{}", c.target_dir, c.filename).replace("{platform}", p.name());
+        clean_part_files(normalize(&template)).await;
+    }
+    let progress_handle = tokio::spawn(PROGRESS.run());
     let mut set: JoinSet<()> = JoinSet::new();
-    let (sender, receiver): (Sender<Operation>, Receiver<Operation>) = mpsc::channel(10);
-    let mut worker = Worker::new(&ARGS.database.path, receiver);
-    let worker_handle = tokio::spawn(async move {
-        worker.main().await;
-    });
-    for p in conf.platforms {
-        let client = client.clone();
-        let sender = sender.clone();
-        {
-            set.spawn(async move {
-                p.init(client, sender).await;
-            })
+    // one worker per database, each platform talks to the worker of its database
+    let mut workers: Vec<(PathBuf, Sender<Operation>)> = Vec::new();
+    let mut opened_workers: Vec<Worker> = Vec::new();
+    for p in conf.platforms.iter() {
+        let database = conf.database(p.name());
+        if workers.iter().any(|(path, _)| *path == database) {
+            continue;
+        }
+        let (sender, receiver): (Sender<Operation>, Receiver<Operation>) = mpsc::channel(10);
+        let opened = match database.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => {
+                recursive_dir_create_blocking(parent).map_err(|e| Error::file(parent, e))
+            }
+            _ => Ok(()),
+        }
+        .and_then(|_| Worker::new(&database, receiver));
+        match opened {
+            Ok(w) => opened_workers.push(w),
+            Err(e) => {
+                eprintln!(
+                    "unable to open database: {}\nerror: {e}",
+                    database.display()
+                );
+                exit(2);
+            }
         };
+        workers.push((database, sender));
+    }
+    // every database exists now, each worker looks up duplicates in all of them
+    let mut worker_handles = Vec::new();
+    for mut worker in opened_workers {
+        if let Err(e) = worker.attach(&conf) {
+            eprintln!("unable to attach databases: {e}");
+            exit(2);
+        }
+        worker_handles.push(tokio::spawn(async move {
+            worker.main().await;
+        }));
+    }
+    let senders: Vec<Sender<Operation>> = conf
+        .platforms
+        .iter()
+        .map(|p| {
+            let database = conf.database(p.name());
+            workers
+                .iter()
+                .find(|(path, _)| *path == database)
+                .map(|(_, sender)| sender.clone())
+                .unwrap()
+        })
+        .collect();
+    for (p, sender) in conf.platforms.into_iter().zip(senders) {
+        set.spawn(async move {
+            p.init(sender).await;
+        });
     }
     set.join_all().await;
-    sender.send(Operation::Close).await.unwrap();
-    worker_handle.await.unwrap();
+    for (_, sender) in workers {
+        sender.send(Operation::Close).await.unwrap();
+    }
+    for handle in worker_handles {
+        handle.await.unwrap();
+    }
+    progress_handle.abort();
+    PROGRESS.clear();
     return;
 }
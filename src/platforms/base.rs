@@ -1,12 +1,12 @@
-use std::future::Future;
-
 // crate
-use reqwest::Client;
 use tokio::sync::mpsc;
 
 // local
 use super::{/*Gelbooru, */ Moebooru};
-use crate::{platforms::statics::*, pub_struct, worker::Operation, PlatformConfig};
+use crate::{
+    client::Session, platforms::statics::*, pub_struct, statics::PROGRESS, worker::Operation,
+    PlatformConfig,
+};
 
 pub_struct!(TagMap {
     general: Vec<String>,
@@ -19,6 +19,12 @@ pub_struct!(TagMap {
     style: Vec<String>,
 });
 
+impl Default for TagMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TagMap {
     pub fn new() -> Self {
         Self {
@@ -42,17 +48,39 @@ pub enum Platform {
 }
 
 impl Platform {
-    pub fn init(self, client: Client, worker: mpsc::Sender<Operation>) -> impl Future<Output = ()> {
+    pub fn config(&self) -> &PlatformConfig {
         match self {
-            Platform::Yandere(config) => {
-                Moebooru::new(YANDERE, YANDERE_ROOT, config, worker, client).main()
-            }
-            Platform::Konachan(config) => {
-                Moebooru::new(KONACHAN, KONACHAN_ROOT, config, worker, client).main()
-            }
-            Platform::Sakugabooru(config) => {
-                Moebooru::new(SAKUGABOORU, SAKUGABOORU_ROOT, config, worker, client).main()
-            }
+            Platform::Yandere(config)
+            | Platform::Konachan(config)
+            | Platform::Sakugabooru(config) => config,
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Platform::Yandere(_) => YANDERE,
+            Platform::Konachan(_) => KONACHAN,
+            Platform::Sakugabooru(_) => SAKUGABOORU,
+        }
+    }
+
+    /// runs the platform, it stops on its own when its client can't be set up
+    pub async fn init(self, worker: mpsc::Sender<Operation>) {
+        let name = self.name();
+        let (root, config) = match self {
+            Platform::Yandere(config) => (YANDERE_ROOT, config),
+            Platform::Konachan(config) => (KONACHAN_ROOT, config),
+            Platform::Sakugabooru(config) => (SAKUGABOORU_ROOT, config),
+        };
+        let session = match Session::new(&config, root) {
+            Ok(s) => s,
+            Err(e) => {
+                PROGRESS.eprintln(format!("{name}: {e}"));
+                return;
+            }
+        };
+        Moebooru::new(name, root, config, worker, session)
+            .main()
+            .await;
+    }
 }
@@ -1,24 +1,38 @@
 // std imports
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 // crate imports
-use reqwest::Client;
+use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 use tokio::{
-    sync::{mpsc, oneshot},
-    time::{sleep, Duration},
+    sync::{mpsc, oneshot, Notify},
+    time::{sleep, timeout, Duration},
 };
 
 // local imports
 use crate::{
-    config::{Compress, PlatformConfig},
-    consts::tag_types::*,
+    client::Session,
+    config::{Compress, CrossDuplicate, LowSpace, PlatformConfig},
+    consts::{tag_types::*, LOW_SPACE_WAIT, QUEUE_POLL},
+    dupes,
     fmt::Keywords,
+    import::{self, ImportFile},
+    progress::{human_bytes, Counter},
+    quota::Quota,
     rclone,
-    statics::HOME,
+    retry::{self, RetryPolicy},
+    statics::{ARGS, HOME, PROGRESS, QUOTA, RESERVED_SPACE},
     utils,
-    worker::{DbEntry, ImageRequest, Insert, Operation, Select},
-    Downloader,
+    worker::{
+        CrossEntry, CrossRef, DbEntry, Dequeue, Enqueue, Fail, FailedEntry, Finish, ImageRequest,
+        Insert, Operation, QueueEntry, QueueLength, Resolve, SelectFailed, SelectMany, SelectMd5,
+        SelectNear, SelectPinned,
+    },
+    Command, Downloader, Error,
 };
 
 pub struct Moebooru {
@@ -26,8 +40,16 @@ pub struct Moebooru {
     root: &'static str,
     config: PlatformConfig,
     worker: mpsc::Sender<Operation>,
-    client: Client,
+    session: Session,
     timer: Timer,
+    // byte budget of this platform, the run budget is `QUOTA`
+    quota: Quota,
+    // wakes up download workers waiting for posts
+    queued: Notify,
+    // wakes up the crawler waiting for the workers to catch up
+    claimed: Notify,
+    crawling: AtomicBool,
+    stopped: AtomicBool,
 }
 
 impl Moebooru {
@@ -36,67 +58,113 @@ impl Moebooru {
         root: &'static str,
         config: PlatformConfig,
         worker: mpsc::Sender<Operation>,
-        client: Client,
+        session: Session,
     ) -> Self {
         let timer = Timer {
-            retry_sleep: Duration::from_secs_f32(config.retry_sleep),
+            retry: RetryPolicy::new(
+                config.retries,
+                Duration::from_secs_f32(config.retry_sleep),
+                Duration::from_secs_f32(config.max_retry_sleep),
+            ),
             timeout: Duration::from_secs_f32(config.timeout),
             sleep: Duration::from_secs_f32(config.sleep),
         };
+        let quota = Quota::new(config.budget);
         Self {
             platform,
             root,
             config,
             worker,
-            client,
+            session,
             timer,
+            quota,
+            queued: Notify::new(),
+            claimed: Notify::new(),
+            crawling: AtomicBool::new(true),
+            stopped: AtomicBool::new(false),
         }
     }
 
-    async fn filter(&self, posts: &mut Posts) {
+    fn filter(&self, posts: &mut Posts) {
+        if self.config.blacklist.is_empty() {
+            return;
+        }
         for post in posts.iter_mut() {
-            if self.config.blacklist.len() > 0 {
-                if post.as_ref().is_some_and(|p| {
-                    self.config
-                        .blacklist
-                        .iter()
-                        .map(|t| t.as_str())
-                        .any(|tag| p.tags.contains(tag))
-                }) {
-                    *post = None;
-                    continue;
+            if post.as_ref().is_some_and(|p| {
+                self.config
+                    .blacklist
+                    .iter()
+                    .map(|t| t.as_str())
+                    .any(|tag| p.tags.contains(tag))
+            }) {
+                *post = None;
+            }
+        }
+    }
+
+    /// marks the posts that are already in the database as duplicates
+    async fn lookup(&self, posts: &mut Posts) -> Result<(), Error> {
+        let (comm_send, comm_recv) = oneshot::channel::<HashMap<i64, DbEntry>>();
+        let op = Operation::SelectMany(SelectMany {
+            platform: self.platform,
+            ids: posts.iter().flatten().map(|p| p.id).collect(),
+            sender: comm_send,
+        });
+        self.worker.send(op).await?;
+        let mut entries = comm_recv.await?;
+        for post in posts.iter_mut().flatten() {
+            match entries.remove(&post.id) {
+                Some(db) => {
+                    PROGRESS.println(format!("{}: duplicate: {}", self.platform, post.id));
+                    post.is_duplicate = true;
+                    post.duplicate_entry = Some(db);
                 }
+                None => (),
             }
-            match post {
-                Some(_post) => {
-                    let (comm_send, comm_recv): (
-                        oneshot::Sender<Option<DbEntry>>,
-                        oneshot::Receiver<Option<DbEntry>>,
-                    ) = oneshot::channel();
-                    let op = Operation::Select(Select {
-                        platform: self.platform,
-                        id: _post.id,
-                        sender: comm_send,
-                    });
-                    self.worker.send(op).await.unwrap();
-                    let recv = comm_recv.await.unwrap();
-                    match recv {
-                        Some(db) => {
-                            if db.id == _post.id {
-                                println!("duplicate: {}", _post.id);
-                                _post.is_duplicate = true;
-                                _post.duplicate_entry = Some(db);
-                            }
-                        }
-                        None => (),
+        }
+        return Ok(());
+    }
+
+    /// finds the posts whose file was already saved by another platform
+    async fn lookup_md5(&self, posts: &mut Posts) -> Result<(), Error> {
+        let (comm_send, comm_recv) = oneshot::channel::<HashMap<String, CrossEntry>>();
+        let op = Operation::SelectMd5(SelectMd5 {
+            platform: self.platform,
+            md5s: posts.iter().flatten().map(|p| p.md5.clone()).collect(),
+            sender: comm_send,
+        });
+        self.worker.send(op).await?;
+        let entries = comm_recv.await?;
+        for post in posts.iter_mut().flatten() {
+            match entries.get(&post.md5) {
+                Some(cross) => {
+                    if !post.is_duplicate {
+                        PROGRESS.println(format!(
+                            "{}: post {} is {} post {}",
+                            self.platform, post.id, cross.platform, cross.entry.id
+                        ));
                     }
+                    post.cross_duplicate = Some(cross.clone());
                 }
                 None => (),
             }
         }
+        return Ok(());
     }
 
-    async fn to_keywords<'kw>(&'kw self, post: &'kw Post, tag_map: &'kw TagMap) -> Keywords<'kw> {
+    /// runs the duplicate lookups against the database as it is now
+    async fn mark_duplicates(&self, posts: &mut Posts) -> Result<(), Error> {
+        if self.config.skip {
+            self.lookup(posts).await?;
+        }
+        return self.lookup_md5(posts).await;
+    }
+
+    async fn to_keywords<'kw>(
+        &'kw self,
+        post: &'kw Post,
+        tag_map: &'kw TagMap,
+    ) -> Result<Keywords<'kw>, Error> {
         let mut keywords = Keywords {
             platform: self.platform,
             id: post.id,
@@ -104,10 +172,17 @@ impl Moebooru {
             source: post.source.as_str(),
             md5: post.md5.as_str(),
             file_size: post.file_size,
-            file_ext: if post.file_ext.is_some() {
-                post.file_ext.as_ref().unwrap().as_str()
-            } else {
-                post.file_url.rsplit_once('.').unwrap().1
+            file_ext: match post.file_ext {
+                Some(ref ext) => ext.as_str(),
+                None => match post.file_url.rsplit_once('.') {
+                    Some((_, ext)) => ext,
+                    None => {
+                        return Err(Error::Api(format!(
+                            "unable to find file extension in {}",
+                            post.file_url
+                        )))
+                    }
+                },
             },
             rating: post.rating.as_str(),
             general: Vec::new(),
@@ -130,84 +205,183 @@ impl Moebooru {
                     CIRCLE => keywords.circle.push(tag),
                     FAULTS => keywords.faults.push(tag),
                     STYLE => keywords.style.push(tag),
-                    _ => panic!(
-                        "unexpected tag type for {id} in {platform}: {_type}",
-                        id = post.id,
-                        platform = self.platform,
-                    ),
+                    _ => {
+                        return Err(Error::Api(format!(
+                            "unexpected tag type for {tag}: {_type}"
+                        )))
+                    }
                 },
-                None => panic!(
-                    "couldn't find tag in tag_map: {tag}\npost id: {id}, platform: {platform}",
-                    id = post.id,
-                    platform = self.platform
-                ),
+                None => return Err(Error::Api(format!("couldn't find tag in tag_map: {tag}"))),
             }
         }
-        return keywords;
+        return Ok(keywords);
     }
 
-    async fn handle_duplicate<'dup>(&self, db_entry: DbEntry, duplicate_entry: &DbEntry) -> () {
+    async fn handle_duplicate(
+        &self,
+        db_entry: DbEntry,
+        duplicate_entry: &DbEntry,
+        tags: Vec<(String, Option<&'static str>)>,
+    ) -> Result<(), Error> {
         if db_entry.path != duplicate_entry.path {
             if self.config.to_cloud {
                 let cloud = self.config.cloud.as_str();
-                rclone::moveto(
+                if !rclone::moveto(
                     format!("{cloud}:{src}", src = duplicate_entry.path.as_str(),),
                     format!("{cloud}:{dest}", dest = db_entry.path.as_str(),),
                     || async {},
                 )
-                .await;
+                .await
+                {
+                    return Err(Error::Rclone(format!(
+                        "unable to move {} to {}",
+                        duplicate_entry.path, db_entry.path
+                    )));
+                }
             } else {
                 utils::mvf(
                     duplicate_entry.path.as_str(),
                     db_entry.path.as_str(),
                     || async {},
                 )
-                .await;
+                .await?;
             }
         }
-        if let (Some(old_path), Some(new_path)) = (
+        match (
             duplicate_entry.compress_path.as_ref(),
             db_entry.compress_path.as_ref(),
         ) {
-            if self.config.to_cloud {
-                let cloud = self.config.cloud.as_str();
-                rclone::moveto(
-                    format!("{cloud}:{old_path}"),
-                    format!("{cloud}:{new_path}"),
-                    || async {},
-                )
-                .await;
-            } else {
-                utils::mvf(old_path, new_path, || async {}).await;
+            (Some(old_path), Some(new_path)) if old_path != new_path => {
+                if self.config.to_cloud {
+                    let cloud = self.config.cloud.as_str();
+                    if !rclone::moveto(
+                        format!("{cloud}:{old_path}"),
+                        format!("{cloud}:{new_path}"),
+                        || async {},
+                    )
+                    .await
+                    {
+                        return Err(Error::Rclone(format!(
+                            "unable to move {old_path} to {new_path}"
+                        )));
+                    }
+                } else {
+                    utils::mvf(old_path, new_path, || async {}).await?;
+                }
             }
+            _ => (),
         }
         self.worker
-            .send(Operation::Insert(Insert {
+            .send(Operation::Insert(Box::new(Insert {
                 platform: self.platform,
                 entry: db_entry,
+                tags,
+                crossref: None,
+                imported: false,
+            })))
+            .await?;
+        return Ok(());
+    }
+
+    /// reuses the file of another platform according to `cross_duplicates`,
+    /// returns None when the file has to be downloaded
+    async fn handle_cross_duplicate(
+        &self,
+        cross: &CrossEntry,
+        db_entry: &mut DbEntry,
+    ) -> Result<Option<CrossDuplicate>, Error> {
+        let action = self.config.cross_duplicates;
+        match action {
+            CrossDuplicate::Download => return Ok(None),
+            CrossDuplicate::Skip => {
+                db_entry.path = cross.entry.path.clone();
+                db_entry.compress_path = cross.entry.compress_path.clone();
+                db_entry.dhash = cross.entry.dhash;
+                return Ok(Some(action));
+            }
+            CrossDuplicate::Hardlink | CrossDuplicate::Symlink => {
+                let src = Path::new(cross.entry.path.as_str());
+                // uploaded files can't be linked
+                if self.config.to_cloud || !src.is_file() {
+                    PROGRESS.println(format!(
+                        "{}: post {}: {} is not a local file, downloading",
+                        self.platform, db_entry.id, cross.entry.path
+                    ));
+                    return Ok(None);
+                }
+                let dest = Path::new(db_entry.path.as_str());
+                if src != dest {
+                    if let Some(parent) = dest.parent() {
+                        utils::recursive_dir_create(parent)
+                            .await
+                            .map_err(|e| Error::file(parent, e))?;
+                    }
+                    match tokio::fs::remove_file(dest).await {
+                        Ok(_) => (),
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                        Err(e) => return Err(Error::file(dest, e)),
+                    }
+                    let linked = match action {
+                        CrossDuplicate::Hardlink => tokio::fs::hard_link(src, dest).await,
+                        _ => tokio::fs::symlink(src, dest).await,
+                    };
+                    linked.map_err(|e| Error::file(dest, e))?;
+                }
+                db_entry.compress_path = cross.entry.compress_path.clone();
+                db_entry.dhash = cross.entry.dhash;
+                return Ok(Some(action));
+            }
+        }
+    }
+
+    async fn is_pinned(&self, id: i64) -> Result<bool, Error> {
+        let (send, recv) = oneshot::channel::<bool>();
+        self.worker
+            .send(Operation::SelectPinned(SelectPinned {
+                platform: self.platform,
+                id,
+                sender: send,
             }))
-            .await
-            .unwrap();
+            .await?;
+        return Ok(recv.await?);
+    }
+
+    /// a saved image within `near_distance` of `dhash` with a higher resolution than the post
+    async fn near_duplicate(
+        &self,
+        dhash: i64,
+        db_entry: &DbEntry,
+    ) -> Result<Option<CrossEntry>, Error> {
+        let (send, recv) = oneshot::channel::<Option<CrossEntry>>();
+        self.worker
+            .send(Operation::SelectNear(SelectNear {
+                platform: self.platform,
+                id: db_entry.id,
+                dhash,
+                distance: self.config.near_distance,
+                sender: send,
+            }))
+            .await?;
+        return Ok(recv
+            .await?
+            .filter(|near| near.entry.resolution() > db_entry.resolution()));
     }
 
     async fn handle_compression(
         &self,
-        file: &PathBuf,
+        file: &Path,
         compress: &Compress,
         keywords: &Keywords<'_>,
         db_entry: &mut DbEntry,
-    ) {
+    ) -> Result<(), Error> {
         let path = [compress.target_dir.as_str(), compress.filename.as_str()]
             .iter()
             .map(|s| keywords.format(s))
-            .collect::<Vec<String>>();
-        let (local_send, local_recv): (
-            oneshot::Sender<Option<PathBuf>>,
-            oneshot::Receiver<Option<PathBuf>>,
-        ) = oneshot::channel();
+            .collect::<Result<Vec<String>, Error>>()?;
+        let (local_send, local_recv) = oneshot::channel::<Result<PathBuf, Error>>();
         self.worker
             .send(Operation::Image(ImageRequest {
-                src: file.clone(),
+                src: file.to_path_buf(),
                 dest: path.clone(),
                 size: compress.size,
                 fallback: match self.config.to_cloud {
@@ -216,41 +390,94 @@ impl Moebooru {
                 },
                 response_channel: local_send,
             }))
+            .await?;
+        let file = local_recv.await??;
+        if self.config.to_cloud {
+            let _dest_path = path.join("/");
+            if rclone::copyto(
+                file.to_string_lossy().as_ref(),
+                format!("{}:{}", self.config.cloud.as_str(), _dest_path.as_str()).as_str(),
+                //_dest_path.as_str(),
+                self.config.delete,
+                || async {},
+            )
             .await
-            .unwrap();
-        let resp = local_recv.await.unwrap();
-        match resp {
-            Some(file) => {
-                if self.config.to_cloud {
-                    let _dest_path = path.join("/");
-                    if rclone::copyto(
-                        file.to_str().unwrap(),
-                        format!("{}:{}", self.config.cloud.as_str(), _dest_path.as_str()).as_str(),
-                        //_dest_path.as_str(),
-                        self.config.delete,
-                        || async {},
-                    )
-                    .await
-                    {
-                        db_entry.compress_path = Some(_dest_path);
+            {
+                db_entry.compress_path = Some(_dest_path);
+            }
+        } else {
+            db_entry.compress_path = Some(file.to_string_lossy().to_string());
+        }
+        return Ok(());
+    }
+
+    /// makes sure `size` bytes plus the configured reserve fit in `dir` next to the other
+    /// downloads in progress, waits for space or fails depending on `low_space`.
+    /// `size` is reserved until `release_space` is called.
+    async fn check_space(&self, dir: &str, size: u64) -> Result<(), Error> {
+        let required = size.saturating_add(self.config.reserve);
+        let mut paused = false;
+        loop {
+            let available = {
+                // checked and reserved at once, other workers could take the space in between
+                let mut reserved = RESERVED_SPACE.lock().unwrap();
+                let available = utils::available_space(dir)
+                    .map_err(|e| Error::file(dir, e))?
+                    .saturating_sub(*reserved);
+                if available >= required {
+                    *reserved += size;
+                }
+                available
+            };
+            if available >= required {
+                if paused {
+                    PROGRESS.println(format!("{}: resuming", self.platform));
+                }
+                return Ok(());
+            }
+            let e = Error::DiskSpace {
+                path: dir.into(),
+                available,
+                required,
+            };
+            match self.config.low_space {
+                LowSpace::Skip => return Err(e),
+                LowSpace::Pause => {
+                    if !paused {
+                        PROGRESS.println(format!("{}: {e}, waiting", self.platform));
+                        paused = true;
                     }
-                } else {
-                    db_entry.compress_path = Some(file.to_str().unwrap().to_string());
+                    sleep(LOW_SPACE_WAIT).await;
                 }
             }
-            None => (),
         }
     }
 
-    #[allow(unused_assignments)]
-    async fn post_task(&self, post: Post, tag_map: &TagMap) {
-        let mut is_success = false;
-        let keywords = self.to_keywords(&post, tag_map).await;
-        let target_dir: String = keywords.format(self.config.target_dir.as_str());
-        let filename: String = keywords.format(self.config.filename.as_str());
-        let full_path_vec = Vec::from([target_dir.as_str(), filename.as_str()]);
-        let full_path = full_path_vec.join("/");
-        let mut db_entry = DbEntry {
+    /// the download reserved by `check_space` is done, its file is on disk or it failed
+    fn release_space(&self, size: u64) {
+        let mut reserved = RESERVED_SPACE.lock().unwrap();
+        *reserved = reserved.saturating_sub(size);
+    }
+
+    /// takes `size` bytes from both the run and the platform budget
+    fn take_budget(&self, size: u64) -> Result<(), Error> {
+        if !QUOTA.take(size) {
+            return Err(Error::Budget);
+        }
+        if !self.quota.take(size) {
+            QUOTA.give_back(size);
+            return Err(Error::Budget);
+        }
+        return Ok(());
+    }
+
+    fn give_back_budget(&self, size: u64) {
+        QUOTA.give_back(size);
+        self.quota.give_back(size);
+    }
+
+    fn to_db_entry(&self, post: &Post, keywords: &Keywords<'_>, path: String) -> DbEntry {
+        return DbEntry {
             id: post.id,
             md5: keywords.md5.to_string(),
             source: if post.source.is_empty() {
@@ -263,122 +490,730 @@ impl Moebooru {
             } else {
                 Some(post.tags.clone())
             },
-            path: full_path.clone(),
+            path,
             compress_path: None,
+            rating: Some(post.rating.clone()),
+            score: Some(post.score),
+            width: Some(post.width as i64),
+            height: Some(post.height as i64),
+            file_size: Some(post.file_size),
+            file_ext: Some(keywords.file_ext.to_string()),
+            created_at: Some(post.created_at),
+            updated_at: post.updated_at,
+            author: Some(post.author.clone()),
+            parent_id: post.parent_id.map(|id| id as i64),
+            status: Some(post.status.as_str().to_string()),
+            file_url: Some(post.file_url.clone()),
+            dhash: None,
         };
-        if post.is_duplicate {
-            match post.duplicate_entry {
-                Some(ref duplicate_entry) => {
-                    if db_entry != *duplicate_entry {
-                        return self.handle_duplicate(db_entry.clone(), duplicate_entry).await;
-                    }
+    }
 
-                },
-                None => panic!("unexpected event: is_duplicate is true but encountered None for db_entry\npost: {:?}", post),
+    /// perceptual hash of an image, None for other files or when it can't be decoded
+    async fn hash_image(&self, file: &Path, ext: &str, id: i64) -> Option<i64> {
+        if !dupes::is_image(ext) {
+            return None;
+        }
+        let path = file.to_path_buf();
+        match tokio::task::spawn_blocking(move || dupes::dhash(path)).await {
+            Ok(Ok(hash)) => return Some(hash),
+            Ok(Err(e)) => PROGRESS.eprintln(format!(
+                "{platform}: post {id}: unable to hash image: {e}",
+                platform = self.platform,
+            )),
+            Err(e) => PROGRESS.eprintln(format!("image hash panicked: {e}")),
+        }
+        return None;
+    }
+
+    async fn post_task(&self, post: Post, tag_map: &TagMap) -> Result<Counter, Error> {
+        let keywords = self.to_keywords(&post, tag_map).await?;
+        let target_dir: String = keywords.format(self.config.target_dir.as_str())?;
+        let filename: String = keywords.format(self.config.filename.as_str())?;
+        let full_path_vec = Vec::from([target_dir.as_str(), filename.as_str()]);
+        let full_path = full_path_vec.join("/");
+        let mut db_entry = self.to_db_entry(&post, &keywords, full_path.clone());
+        if let Some(ref duplicate_entry) = post.duplicate_entry {
+            // duplicates aren't compressed again, keep the existing copy
+            db_entry.compress_path = duplicate_entry.compress_path.clone();
+            db_entry.dhash = duplicate_entry.dhash;
+            // skipped for another platform's file, which must not be moved
+            if post
+                .cross_duplicate
+                .as_ref()
+                .is_some_and(|cross| cross.entry.path == duplicate_entry.path)
+            {
+                db_entry.path = duplicate_entry.path.clone();
+            }
+            // other posts may point to the file and moving it would leave them without one,
+            // imported files stay where they were found
+            if db_entry.path != duplicate_entry.path && self.is_pinned(post.id).await? {
+                db_entry.path = duplicate_entry.path.clone();
+            }
+            if db_entry != *duplicate_entry {
+                self.handle_duplicate(db_entry.clone(), duplicate_entry, keywords.typed_tags())
+                    .await?;
+                return Ok(Counter::Done);
+            }
+            return Ok(Counter::Skipped);
+        }
+
+        let mut crossref: Option<CrossRef> = None;
+        if let Some(ref cross) = post.cross_duplicate {
+            let action = match self.handle_cross_duplicate(cross, &mut db_entry).await {
+                Ok(action) => action,
+                Err(e) => {
+                    PROGRESS.eprintln(format!(
+                        "{platform}: post {id}: unable to reuse {path}, downloading: {e}",
+                        platform = self.platform,
+                        id = post.id,
+                        path = cross.entry.path,
+                    ));
+                    None
+                }
+            };
+            crossref = Some(CrossRef {
+                platform: cross.platform.clone(),
+                id: cross.entry.id,
+                action: action.unwrap_or(CrossDuplicate::Download).as_str(),
+            });
+            if action.is_some() {
+                self.worker
+                    .send(Operation::Insert(Box::new(Insert {
+                        platform: self.platform,
+                        entry: db_entry,
+                        tags: keywords.typed_tags(),
+                        crossref,
+                        imported: false,
+                    })))
+                    .await?;
+                return Ok(Counter::Skipped);
             }
         }
 
-        let downloaded = Downloader::new(
-            self.client.clone(),
+        let size = u64::try_from(post.file_size).unwrap_or(0);
+        self.check_space(target_dir.as_str(), size).await?;
+        if let Err(e) = self.take_budget(size) {
+            self.release_space(size);
+            return Err(e);
+        }
+        let download = Downloader::new(
+            &self.session,
             post.file_url.as_str(),
             full_path_vec,
             Some(HOME.as_str()),
             self.timer.timeout,
-            self.config.retries,
-            self.timer.retry_sleep,
+            self.timer.retry,
         )
+        .with_expected_size(usize::try_from(post.file_size).ok())
+        .with_md5(Some(post.md5.as_str()))
+        .with_segments(self.config.segments, self.config.segment_threshold)
         .download()
         .await;
-        match downloaded {
-            Some(file) => {
-                match self.config.compress {
-                    Some(ref compress) => {
-                        self.handle_compression(&file, compress, &keywords, &mut db_entry)
-                            .await
-                    }
-                    None => (),
-                }
-                if self.config.to_cloud {
-                    is_success = rclone::copyto(
-                        file.to_str().unwrap(),
-                        format!("{}:{}", self.config.cloud.as_str(), full_path.as_str()).as_str(),
-                        self.config.delete,
-                        || async {},
-                    )
-                    .await;
-                } else {
-                    is_success = true;
-                }
+        self.release_space(size);
+        let (file, transferred) = match download {
+            Ok(f) => f,
+            Err(e) => {
+                self.give_back_budget(size);
+                return Err(e);
+            }
+        };
+        // only what was received counts, not files that exist or resumed parts
+        self.give_back_budget(size.saturating_sub(transferred));
+        db_entry.dhash = self.hash_image(&file, keywords.file_ext, post.id).await;
+        if let Some(dhash) = db_entry.dhash.filter(|_| self.config.skip_near_duplicates) {
+            if let Some(near) = self.near_duplicate(dhash, &db_entry).await? {
+                PROGRESS.println(format!(
+                    "{}: post {} is a near duplicate of {} post {}, removing it",
+                    self.platform, post.id, near.platform, near.entry.id
+                ));
+                tokio::fs::remove_file(&file)
+                    .await
+                    .map_err(|e| Error::file(&file, e))?;
+                db_entry.path = near.entry.path.clone();
+                db_entry.compress_path = near.entry.compress_path.clone();
+                self.worker
+                    .send(Operation::Insert(Box::new(Insert {
+                        platform: self.platform,
+                        entry: db_entry,
+                        tags: keywords.typed_tags(),
+                        crossref: Some(CrossRef {
+                            platform: near.platform,
+                            id: near.entry.id,
+                            action: "near_duplicate",
+                        }),
+                        imported: false,
+                    })))
+                    .await?;
+                return Ok(Counter::Skipped);
             }
-            None => return,
         }
-
-        if is_success {
-            self.worker
-                .send(Operation::Insert(Insert {
-                    platform: self.platform,
-                    entry: db_entry,
-                }))
+        if let Some(ref compress) = self.config.compress {
+            // the original is still saved when compression fails
+            if let Err(e) = self
+                .handle_compression(&file, compress, &keywords, &mut db_entry)
                 .await
-                .unwrap();
+            {
+                PROGRESS.eprintln(format!(
+                    "{platform}: post {id}: compression failed: {e}",
+                    platform = self.platform,
+                    id = post.id
+                ));
+            }
         }
+        if self.config.to_cloud
+            && !rclone::copyto(
+                file.to_string_lossy().as_ref(),
+                format!("{}:{}", self.config.cloud.as_str(), full_path.as_str()).as_str(),
+                self.config.delete,
+                || async {},
+            )
+            .await
+        {
+            return Err(Error::Rclone(format!("unable to upload {full_path}")));
+        }
+        self.worker
+            .send(Operation::Insert(Box::new(Insert {
+                platform: self.platform,
+                entry: db_entry,
+                tags: keywords.typed_tags(),
+                crossref,
+                imported: false,
+            })))
+            .await?;
+        return Ok(Counter::Done);
     }
 
-    async fn tag_task<T: Serialize>(&self, params: &T, client: &Client) -> Option<(Posts, TagMap)> {
-        loop {
-            let mut response: serde_json::Value = {
-                loop {
-                    match client.get(self.root).query(params).send().await {
+    async fn tag_task<T: Serialize>(&self, params: &T) -> Result<Option<(Posts, TagMap)>, Error> {
+        let mut backoff = self.timer.retry.backoff();
+        let mut response: serde_json::Value = loop {
+            match self
+                .session
+                .get(self.root)
+                .query(params)
+                .timeout(self.timer.timeout)
+                .send()
+                .await
+            {
+                Ok(r) => {
+                    self.session.store_cookies(&r);
+                    let status = r.status();
+                    if retry::is_throttled(status) {
+                        let retry_after = retry::retry_after(r.headers());
+                        backoff.wait(status, retry_after).await?;
+                        continue;
+                    }
+                    // retrying won't fix a bad api_key or a missing endpoint
+                    if status.is_client_error() {
+                        return Err(Error::Status(status, self.root.to_string()));
+                    }
+                    match r.error_for_status() {
                         Ok(r) => match r.json().await {
                             Ok(j) => break j,
-                            Err(e) => {
-                                eprintln!("{e:?}");
-                                continue;
-                            }
+                            Err(e) => backoff.wait(e, None).await?,
                         },
-                        Err(e) => {
-                            eprintln!("{e:?}");
-                            continue;
-                        }
+                        Err(e) => backoff.wait(e, None).await?,
                     }
                 }
-            };
-            let mut posts: Posts = match serde_json::from_value(response["posts"].take()) {
-                Ok(p) => p,
-                Err(e) => panic!("{e:?}\nplatform: {}", self.platform),
-            };
-            let posts_len = posts.len();
-            if posts_len == 0 {
-                return None;
+                Err(e) => backoff.wait(e, None).await?,
             }
-            if self.config.skip {
-                self.filter(&mut posts).await;
+        };
+        let mut posts: Posts = serde_json::from_value(response["posts"].take())?;
+        if posts.is_empty() {
+            return Ok(None);
+        }
+        self.filter(&mut posts);
+        let tag_map: TagMap = serde_json::from_value(response["tags"].take())?;
+        return Ok(Some((posts, tag_map)));
+    }
+
+    /// runs a post, failures are counted and recorded in the failed ledger
+    async fn handle_post(&self, post: Post, tag_map: &TagMap) -> Result<Counter, Error> {
+        sleep(self.timer.sleep).await;
+        let (id, url) = (post.id, post.file_url.clone());
+        let e = match self.post_task(post, tag_map).await {
+            Ok(counter) => {
+                PROGRESS.count(self.platform, counter);
+                return Ok(counter);
             }
-            let tag_map: TagMap = serde_json::from_value(response["tags"].take()).unwrap();
-            return Some((posts, tag_map));
+            Err(e) => e,
+        };
+        // not a failure of the post, the platform stops here
+        if let Error::Budget = e {
+            return Err(e);
         }
+        PROGRESS.eprintln(format!(
+            "{platform}: post {id}: {e}",
+            platform = self.platform
+        ));
+        PROGRESS.count(self.platform, Counter::Failed);
+        if let Error::Channel = e {
+            return Err(e);
+        }
+        self.worker
+            .send(Operation::Fail(Fail {
+                platform: self.platform,
+                id,
+                url,
+                reason: e.to_string(),
+            }))
+            .await?;
+        return Err(e);
     }
 
-    pub async fn main(self) {
+    /// stops the crawler and the download workers, returns false if they were already stopped
+    fn stop(&self) -> bool {
+        self.queued.notify_waiters();
+        self.claimed.notify_waiters();
+        return !self.stopped.swap(true, Ordering::AcqRel);
+    }
+
+    fn budget_used_up(&self) {
+        PROGRESS.println(format!(
+            "{platform}: byte budget is used up, stopping. run: {run}, platform: {platform_used}",
+            platform = self.platform,
+            run = human_bytes(QUOTA.used() as f64),
+            platform_used = human_bytes(self.quota.used() as f64),
+        ));
+    }
+
+    /// crawls the tags and queues their posts for the download workers
+    async fn crawl(&self) {
         let mut params = Params::default();
-        for tag in self.config.tags.iter().map(|t| t.as_str()) {
-            println!("{}: {}", self.platform, tag);
+        'tags: for tag in self.config.tags.iter().map(|t| t.as_str()) {
+            PROGRESS.set_tag(self.platform, tag);
             (params.page, params.tags) = (0, tag);
-            while let Some((posts, tag_map)) = {
+            loop {
+                if let Err(e) = self.wait_for_downloads(params.limit).await {
+                    PROGRESS.eprintln(format!("{}: {e}", self.platform));
+                    break 'tags;
+                }
+                if self.stopped.load(Ordering::Acquire) {
+                    break 'tags;
+                }
                 params.page += 1;
-                self.tag_task(&params, &self.client).await
-            } {
+                PROGRESS.set_page(self.platform, params.page);
+                let (posts, tag_map) = match self.tag_task(&params).await {
+                    Ok(Some(page)) => page,
+                    Ok(None) => break,
+                    Err(e) => {
+                        PROGRESS.eprintln(format!(
+                            "{platform}: {tag}: page {page}: {e}",
+                            platform = self.platform,
+                            page = params.page,
+                        ));
+                        match e {
+                            Error::Channel => break 'tags,
+                            _ => break,
+                        }
+                    }
+                };
                 for post in posts {
                     match post {
                         Some(p) if p.status != Status::Deleted => {
-                            sleep(self.timer.sleep).await;
-                            self.post_task(p, &tag_map).await;
+                            if let Err(e) = self.enqueue(p, &tag_map).await {
+                                PROGRESS.eprintln(format!("{}: {e}", self.platform));
+                                break 'tags;
+                            }
+                        }
+                        _ => PROGRESS.count(self.platform, Counter::Skipped),
+                    }
+                }
+            }
+        }
+        self.crawling.store(false, Ordering::Release);
+        self.queued.notify_waiters();
+    }
+
+    /// keeps the crawler at most `prefetch_pages` pages ahead of the download workers
+    async fn wait_for_downloads(&self, page_size: u8) -> Result<(), Error> {
+        let ahead = (self.config.prefetch_pages * page_size as usize) as i64;
+        while !self.stopped.load(Ordering::Acquire) {
+            let (send, recv) = oneshot::channel::<i64>();
+            self.worker
+                .send(Operation::QueueLength(QueueLength {
+                    platform: self.platform,
+                    sender: send,
+                }))
+                .await?;
+            if recv.await? <= ahead {
+                break;
+            }
+            timeout(QUEUE_POLL, self.claimed.notified()).await.ok();
+        }
+        return Ok(());
+    }
+
+    async fn enqueue(&self, post: Post, tag_map: &TagMap) -> Result<(), Error> {
+        // only the tags of this post are needed to build its keywords
+        let tags: TagMap = post
+            .tags
+            .split_whitespace()
+            .filter_map(|tag| tag_map.get_key_value(tag))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        let id = post.id;
+        let post = serde_json::to_string(&QueuedPost { post, tags })?;
+        self.worker
+            .send(Operation::Enqueue(Enqueue {
+                platform: self.platform,
+                id,
+                post,
+            }))
+            .await?;
+        self.queued.notify_waiters();
+        return Ok(());
+    }
+
+    /// claims the next queued post, None when the queue is empty
+    async fn dequeue(&self) -> Result<Option<QueueEntry>, Error> {
+        let (send, recv) = oneshot::channel::<Option<QueueEntry>>();
+        self.worker
+            .send(Operation::Dequeue(Dequeue {
+                platform: self.platform,
+                sender: send,
+            }))
+            .await?;
+        return Ok(recv.await?);
+    }
+
+    /// downloads queued posts until the queue is empty and the crawler is done.
+    /// posts left in the queue are picked up by the next run.
+    async fn download_worker(&self) {
+        loop {
+            if self.stopped.load(Ordering::Acquire) {
+                return;
+            }
+            // read before dequeuing so the last posts of the crawler aren't missed
+            let crawling = self.crawling.load(Ordering::Acquire);
+            let entry = match self.dequeue().await {
+                Ok(Some(entry)) => {
+                    self.claimed.notify_waiters();
+                    entry
+                }
+                Ok(None) if crawling => {
+                    timeout(QUEUE_POLL, self.queued.notified()).await.ok();
+                    continue;
+                }
+                Ok(None) => return,
+                Err(_) => {
+                    self.stop();
+                    return;
+                }
+            };
+            match serde_json::from_str::<QueuedPost>(entry.post.as_str()) {
+                Ok(queued) => match self.handle_queued(queued).await {
+                    Err(Error::Channel) => {
+                        self.stop();
+                        return;
+                    }
+                    Err(Error::Budget) => {
+                        if self.stop() {
+                            self.budget_used_up();
+                        }
+                        return;
+                    }
+                    _ => (),
+                },
+                Err(e) => PROGRESS.eprintln(format!(
+                    "{platform}: post {id}: invalid queue entry: {e}",
+                    platform = self.platform,
+                    id = entry.id
+                )),
+            }
+            let op = Operation::Finish(Finish {
+                platform: self.platform,
+                id: entry.id,
+            });
+            if self.worker.send(op).await.is_err() {
+                self.stop();
+                return;
+            }
+        }
+    }
+
+    /// looks the post up again before running it, the database may have changed since it
+    /// was queued
+    async fn handle_queued(&self, queued: QueuedPost) -> Result<Counter, Error> {
+        let mut posts: Posts = vec![Some(queued.post)];
+        self.mark_duplicates(&mut posts).await?;
+        match posts.pop().flatten() {
+            Some(post) => return self.handle_post(post, &queued.tags).await,
+            None => return Ok(Counter::Skipped),
+        }
+    }
+
+    /// re-runs the posts in the failed ledger, each one is fetched again with an `id:` query
+    async fn retry_failed(&self) {
+        let (send, recv) = oneshot::channel::<Vec<FailedEntry>>();
+        let op = Operation::SelectFailed(SelectFailed {
+            platform: self.platform,
+            sender: send,
+        });
+        if self.worker.send(op).await.is_err() {
+            return;
+        }
+        let failed = match recv.await {
+            Ok(f) => f,
+            Err(_) => return,
+        };
+        for entry in failed {
+            let query = format!("id:{}", entry.id);
+            PROGRESS.set_tag(self.platform, query.as_str());
+            PROGRESS.println(format!(
+                "{platform}: retrying post {id}, failed {attempts} time(s): {reason}",
+                platform = self.platform,
+                id = entry.id,
+                attempts = entry.attempts,
+                reason = entry.reason,
+            ));
+            let params = Params {
+                page: 1,
+                tags: query.as_str(),
+                ..Default::default()
+            };
+            let (mut posts, tag_map) = match self.tag_task(&params).await {
+                Ok(Some(page)) => page,
+                Ok(None) => (Vec::new(), TagMap::new()),
+                Err(e) => {
+                    PROGRESS.eprintln(format!(
+                        "{platform}: post {id}: {e}",
+                        platform = self.platform,
+                        id = entry.id
+                    ));
+                    match e {
+                        Error::Channel => break,
+                        _ => continue,
+                    }
+                }
+            };
+            // saved posts are in the ledger when their file is missing or broken, they're
+            // downloaded again instead of being skipped as duplicates so `lookup` isn't run
+            if self.lookup_md5(&mut posts).await.is_err() {
+                break;
+            }
+            match posts.into_iter().flatten().find(|p| p.id == entry.id) {
+                Some(p) if p.status != Status::Deleted => {
+                    match self.handle_post(p, &tag_map).await {
+                        // already saved, nothing was inserted to clear the ledger
+                        Ok(Counter::Skipped) => {
+                            let op = Operation::Resolve(Resolve {
+                                platform: self.platform,
+                                id: entry.id,
+                            });
+                            if self.worker.send(op).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(Error::Channel) => break,
+                        Err(Error::Budget) => {
+                            self.budget_used_up();
+                            break;
                         }
                         _ => (),
                     }
                 }
+                _ => {
+                    PROGRESS.println(format!(
+                        "{platform}: post {id} is no longer available, removing it from failed posts",
+                        platform = self.platform,
+                        id = entry.id
+                    ));
+                    PROGRESS.count(self.platform, Counter::Skipped);
+                    let op = Operation::Resolve(Resolve {
+                        platform: self.platform,
+                        id: entry.id,
+                    });
+                    if self.worker.send(op).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// records the files of the `import` command that belong to this platform
+    async fn import(&self) {
+        // the paths of these platforms are on the remote
+        if self.config.to_cloud && (!ARGS.move_files || ARGS.offline) {
+            PROGRESS.eprintln(format!(
+                "{}: files are only imported online with --move when uploading to the cloud",
+                self.platform
+            ));
+            return;
+        }
+        for file in import::files().await {
+            // files named after a post of another platform
+            if file.platform.is_some_and(|p| p != self.platform) {
+                continue;
+            }
+            let result = match ARGS.offline {
+                true => self.import_offline(file).await,
+                false => self.import_file(file).await,
+            };
+            match result {
+                Ok(counter) => PROGRESS.count(self.platform, counter),
+                Err(Error::Channel) => break,
+                Err(e) => {
+                    PROGRESS.eprintln(format!("{}: {}: {e}", self.platform, file.path.display()));
+                    PROGRESS.count(self.platform, Counter::Failed);
+                }
+            }
+        }
+    }
+
+    /// finds the post of a file by its id or md5, None when this platform doesn't have it
+    async fn find_post(&self, file: &ImportFile) -> Result<Option<(Post, TagMap)>, Error> {
+        let query = match file.id {
+            Some(id) => format!("id:{id}"),
+            None => format!("md5:{}", file.md5),
+        };
+        let params = Params {
+            page: 1,
+            tags: query.as_str(),
+            ..Default::default()
+        };
+        sleep(self.timer.sleep).await;
+        let (mut posts, tag_map) = match self.tag_task(&params).await? {
+            Some(page) => page,
+            None => return Ok(None),
+        };
+        // imported files are never saved twice, whether `skip` is set or not
+        self.lookup(&mut posts).await?;
+        self.lookup_md5(&mut posts).await?;
+        match posts.into_iter().flatten().find(|p| p.md5 == file.md5) {
+            Some(post) => return Ok(Some((post, tag_map))),
+            None => {
+                if let Some(id) = file.id {
+                    PROGRESS.println(format!(
+                        "{}: {} doesn't match the md5 of post {id}",
+                        self.platform,
+                        file.path.display()
+                    ));
+                }
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn import_file(&self, file: &ImportFile) -> Result<Counter, Error> {
+        let (post, tag_map) = match self.find_post(file).await? {
+            Some(found) => found,
+            None => return Ok(Counter::Skipped),
+        };
+        if post.is_duplicate || !import::claim(&file.path) {
+            return Ok(Counter::Skipped);
+        }
+        let keywords = self.to_keywords(&post, &tag_map).await?;
+        let dhash = self
+            .hash_image(&file.path, keywords.file_ext, post.id)
+            .await;
+        let src = file.path.to_string_lossy();
+        let path = match ARGS.move_files {
+            true => {
+                let path = [
+                    keywords.format(self.config.target_dir.as_str())?,
+                    keywords.format(self.config.filename.as_str())?,
+                ]
+                .join("/");
+                if self.config.to_cloud {
+                    let dest = format!("{}:{path}", self.config.cloud);
+                    if !rclone::copyto(src.as_ref(), dest.as_str(), true, || async {}).await {
+                        return Err(Error::Rclone(format!("unable to upload {path}")));
+                    }
+                } else if src != path {
+                    utils::mvf(src.as_ref(), path.as_str(), || async {}).await?;
+                }
+                path
+            }
+            false => tokio::fs::canonicalize(&file.path)
+                .await
+                .map_err(|e| Error::file(&file.path, e))?
+                .to_string_lossy()
+                .to_string(),
+        };
+        let mut db_entry = self.to_db_entry(&post, &keywords, path);
+        db_entry.dhash = dhash;
+        PROGRESS.println(format!(
+            "{}: imported post {} from {}",
+            self.platform,
+            post.id,
+            file.path.display()
+        ));
+        self.worker
+            .send(Operation::Insert(Box::new(Insert {
+                platform: self.platform,
+                entry: db_entry,
+                tags: keywords.typed_tags(),
+                crossref: None,
+                imported: !ARGS.move_files,
+            })))
+            .await?;
+        return Ok(Counter::Done);
+    }
+
+    /// imports a file named after its post with what the name tells, the file stays in place
+    async fn import_offline(&self, file: &ImportFile) -> Result<Counter, Error> {
+        let id = match file.id {
+            Some(id) => id,
+            None => return Ok(Counter::Skipped),
+        };
+        let (send, recv) = oneshot::channel::<HashMap<i64, DbEntry>>();
+        self.worker
+            .send(Operation::SelectMany(SelectMany {
+                platform: self.platform,
+                ids: vec![id],
+                sender: send,
+            }))
+            .await?;
+        if !recv.await?.is_empty() || !import::claim(&file.path) {
+            return Ok(Counter::Skipped);
+        }
+        let path = tokio::fs::canonicalize(&file.path)
+            .await
+            .map_err(|e| Error::file(&file.path, e))?;
+        let ext = file.ext.clone().unwrap_or_default();
+        let db_entry = DbEntry {
+            id,
+            md5: file.md5.clone(),
+            tags: file.tags.clone(),
+            path: path.to_string_lossy().to_string(),
+            file_size: i64::try_from(file.file_size).ok(),
+            dhash: self.hash_image(&path, ext.as_str(), id).await,
+            file_ext: file.ext.clone(),
+            ..Default::default()
+        };
+        let tags = file
+            .tags
+            .iter()
+            .flat_map(|t| t.split_whitespace())
+            .map(|t| (t.to_string(), None))
+            .collect();
+        self.worker
+            .send(Operation::Insert(Box::new(Insert {
+                platform: self.platform,
+                entry: db_entry,
+                tags,
+                crossref: None,
+                imported: true,
+            })))
+            .await?;
+        return Ok(Counter::Done);
+    }
+
+    pub async fn main(self) {
+        match ARGS.command {
+            Command::Run => {
+                let workers =
+                    (0..self.config.download_workers.max(1)).map(|_| self.download_worker());
+                tokio::join!(self.crawl(), join_all(workers));
             }
+            Command::RetryFailed => self.retry_failed().await,
+            Command::Import => self.import().await,
+            // handled in main without starting the platforms
+            Command::Search | Command::Dupes | Command::Verify | Command::Gc => (),
         }
+        self.session.cookies.save();
+        PROGRESS.remove_platform(self.platform);
     }
 }
 
@@ -386,7 +1221,7 @@ type TagMap = std::collections::BTreeMap<String, String>;
 type Posts = Vec<Option<Post>>;
 
 struct Timer {
-    retry_sleep: Duration,
+    retry: RetryPolicy,
     timeout: Duration,
     sleep: Duration,
 }
@@ -412,7 +1247,14 @@ impl<'p> Default for Params<'p> {
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+/// a post in the download queue with the types of its tags
+#[derive(Debug, Serialize, Deserialize)]
+struct QueuedPost {
+    post: Post,
+    tags: TagMap,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
 enum Status {
     Active,
@@ -421,7 +1263,18 @@ enum Status {
     Deleted,
 }
 
-#[derive(Debug, Deserialize)]
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Active => "active",
+            Status::Pending => "pending",
+            Status::Flagged => "flagged",
+            Status::Deleted => "deleted",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 #[allow(dead_code)]
 struct FlagDetail {
     pub post_id: i64,
@@ -429,7 +1282,7 @@ struct FlagDetail {
     pub created_at: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[allow(dead_code)]
 struct Post {
     pub id: i64,
@@ -480,8 +1333,12 @@ struct Post {
     pub last_noted_at: Option<u64>,
     pub last_commented_at: Option<u64>,
     pub flag_detail: Option<FlagDetail>,
-    #[serde(skip, default = "bool::default")]
+    // set by `lookup`, not queued since the database changes while the post waits
+    #[serde(skip)]
     pub is_duplicate: bool,
-    #[serde(skip, default = "Option::default")]
+    #[serde(skip)]
     pub duplicate_entry: Option<DbEntry>,
+    // set by `lookup_md5`, not queued either
+    #[serde(skip)]
+    pub cross_duplicate: Option<CrossEntry>,
 }
@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{stderr, stdout, IsTerminal, Write},
+    sync::Mutex,
+    time::Instant,
+};
+
+// crate
+use tokio::time::{sleep, Duration};
+
+const REFRESH: Duration = Duration::from_millis(500);
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// live status of platforms and active downloads.
+/// redrawn in place when stdout is a terminal, plain line logs otherwise.
+pub struct Progress {
+    state: Mutex<State>,
+    is_tty: bool,
+}
+
+#[derive(Default)]
+struct State {
+    platforms: BTreeMap<&'static str, PlatformState>,
+    downloads: BTreeMap<u64, DownloadState>,
+    next_id: u64,
+    // number of lines drawn by the last render
+    lines: usize,
+}
+
+#[derive(Default)]
+struct PlatformState {
+    tag: String,
+    page: u64,
+    done: u64,
+    skipped: u64,
+    failed: u64,
+}
+
+struct DownloadState {
+    file: String,
+    written: u64,
+    total: Option<u64>,
+    resumed_from: u64,
+    started: Instant,
+}
+
+#[derive(Clone, Copy)]
+pub enum Counter {
+    Done,
+    Skipped,
+    Failed,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            is_tty: stdout().is_terminal(),
+        }
+    }
+
+    /// prints a line above the progress display
+    pub fn println<S: AsRef<str>>(&self, line: S) {
+        let mut state = self.state.lock().unwrap();
+        let mut out = stdout().lock();
+        if self.is_tty {
+            clear(&mut out, state.lines);
+            state.lines = 0;
+        }
+        writeln!(out, "{}", line.as_ref()).ok();
+        out.flush().ok();
+    }
+
+    /// prints a line to stderr above the progress display
+    pub fn eprintln<S: AsRef<str>>(&self, line: S) {
+        let mut state = self.state.lock().unwrap();
+        if self.is_tty {
+            let mut out = stdout().lock();
+            clear(&mut out, state.lines);
+            out.flush().ok();
+            state.lines = 0;
+        }
+        let mut err = stderr().lock();
+        writeln!(err, "{}", line.as_ref()).ok();
+        err.flush().ok();
+    }
+
+    pub fn set_tag(&self, platform: &'static str, tag: &str) {
+        let mut state = self.state.lock().unwrap();
+        let p = state.platforms.entry(platform).or_default();
+        p.tag = tag.to_string();
+        p.page = 0;
+        if !self.is_tty {
+            println!("{platform}: {tag}");
+        }
+    }
+
+    pub fn set_page(&self, platform: &'static str, page: u64) {
+        let mut state = self.state.lock().unwrap();
+        let p = state.platforms.entry(platform).or_default();
+        p.page = page;
+        if !self.is_tty {
+            println!("{platform}: {tag}: page {page}", tag = p.tag);
+        }
+    }
+
+    pub fn count(&self, platform: &'static str, counter: Counter) {
+        let mut state = self.state.lock().unwrap();
+        let p = state.platforms.entry(platform).or_default();
+        match counter {
+            Counter::Done => p.done += 1,
+            Counter::Skipped => p.skipped += 1,
+            Counter::Failed => p.failed += 1,
+        }
+    }
+
+    pub fn remove_platform(&self, platform: &'static str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(p) = state.platforms.remove(platform) {
+            drop(state);
+            self.println(format!(
+                "{platform}: finished, done: {}, skipped: {}, failed: {}",
+                p.done, p.skipped, p.failed
+            ));
+        }
+    }
+
+    /// registers an active download, it's removed from the display when the handle is dropped.
+    pub fn download<S: Into<String>>(&'static self, file: S) -> DownloadHandle {
+        let file: String = file.into();
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        if !self.is_tty {
+            println!("downloading: {file}");
+        }
+        state.downloads.insert(
+            id,
+            DownloadState {
+                file,
+                written: 0,
+                total: None,
+                resumed_from: 0,
+                started: Instant::now(),
+            },
+        );
+        return DownloadHandle { progress: self, id };
+    }
+
+    /// redraws the display until the task is aborted, does nothing when stdout isn't a terminal.
+    pub async fn run(&self) {
+        if !self.is_tty {
+            return;
+        }
+        loop {
+            self.render();
+            sleep(REFRESH).await;
+        }
+    }
+
+    /// removes the display, used before exiting
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        if self.is_tty {
+            let mut out = stdout().lock();
+            clear(&mut out, state.lines);
+            out.flush().ok();
+        }
+        state.lines = 0;
+    }
+
+    fn render(&self) {
+        let mut state = self.state.lock().unwrap();
+        let mut buf = String::new();
+        let mut lines: usize = 0;
+        for (platform, p) in state.platforms.iter() {
+            writeln!(
+                buf,
+                "{platform}: {tag} [page {page}] done: {done}, skipped: {skipped}, failed: {failed}",
+                tag = p.tag,
+                page = p.page,
+                done = p.done,
+                skipped = p.skipped,
+                failed = p.failed,
+            )
+            .ok();
+            lines += 1;
+        }
+        for d in state.downloads.values() {
+            let elapsed = d.started.elapsed().as_secs_f64();
+            let rate = match elapsed > 0.0 {
+                true => d.written.saturating_sub(d.resumed_from) as f64 / elapsed,
+                false => 0.0,
+            };
+            let eta = match (d.total, rate > 0.0) {
+                (Some(total), true) => {
+                    format!("{:.0}s", total.saturating_sub(d.written) as f64 / rate)
+                }
+                _ => "-".to_string(),
+            };
+            writeln!(
+                buf,
+                "  {file}: {written}/{total} {rate}/s eta: {eta}",
+                file = d.file,
+                written = human_bytes(d.written as f64),
+                total = match d.total {
+                    Some(t) => human_bytes(t as f64),
+                    None => "?".to_string(),
+                },
+                rate = human_bytes(rate),
+            )
+            .ok();
+            lines += 1;
+        }
+        let mut out = stdout().lock();
+        clear(&mut out, state.lines);
+        out.write_all(buf.as_bytes()).ok();
+        out.flush().ok();
+        state.lines = lines;
+    }
+}
+
+pub struct DownloadHandle {
+    progress: &'static Progress,
+    id: u64,
+}
+
+impl DownloadHandle {
+    /// called when a (re)connection starts, `written` bytes are already on disk
+    pub fn start(&self, written: u64, total: Option<u64>) {
+        let mut state = self.progress.state.lock().unwrap();
+        if let Some(d) = state.downloads.get_mut(&self.id) {
+            d.written = written;
+            d.resumed_from = written;
+            d.total = total;
+            d.started = Instant::now();
+        }
+    }
+
+    pub fn set(&self, written: u64) {
+        let mut state = self.progress.state.lock().unwrap();
+        if let Some(d) = state.downloads.get_mut(&self.id) {
+            d.written = written;
+        }
+    }
+}
+
+impl Drop for DownloadHandle {
+    fn drop(&mut self) {
+        let mut state = self.progress.state.lock().unwrap();
+        state.downloads.remove(&self.id);
+    }
+}
+
+fn clear<W: Write>(out: &mut W, lines: usize) {
+    if lines > 0 {
+        write!(out, "\x1b[{lines}A\r\x1b[J").ok();
+    }
+}
+
+pub fn human_bytes(bytes: f64) -> String {
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    return format!("{value:.1} {}", UNITS[unit]);
+}
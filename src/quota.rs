@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// byte budget that can be shared between platform tasks
+pub struct Quota {
+    // u64::MAX when there's no limit
+    limit: AtomicU64,
+    used: AtomicU64,
+}
+
+impl Default for Quota {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Quota {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit: AtomicU64::new(limit.unwrap_or(u64::MAX)),
+            used: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_limit(&self, limit: Option<u64>) {
+        self.limit
+            .store(limit.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    /// reserves `bytes`, returns false if that would go over the limit
+    pub fn take(&self, bytes: u64) -> bool {
+        let limit = self.limit.load(Ordering::Relaxed);
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                match used.checked_add(bytes) {
+                    Some(n) if n <= limit => Some(n),
+                    _ => None,
+                }
+            })
+            .is_ok()
+    }
+
+    /// returns bytes reserved by `take` that weren't used, e.g. when a download failed
+    pub fn give_back(&self, bytes: u64) {
+        self.used
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+                Some(used.saturating_sub(bytes))
+            })
+            .ok();
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+}
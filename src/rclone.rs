@@ -2,9 +2,11 @@ use std::future::Future;
 use std::io;
 use std::process::{exit, Stdio};
 use tokio::fs;
-use tokio::io::{stderr, AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncReadExt;
 use tokio::process::Command;
 
+use crate::statics::PROGRESS;
+
 // TODO: use rclone http api and maybe implement proper error enums
 
 /*
@@ -30,7 +32,7 @@ impl std::fmt::Display for RcloneError {
 impl std::error::Error for RcloneError {}
 */
 
-pub async fn copyto<'up, F, Fu, P>(_src: P, _dest: P, delete: bool, on_success: F) -> bool
+pub async fn copyto<F, Fu, P>(_src: P, _dest: P, delete: bool, on_success: F) -> bool
 where
     F: FnOnce() -> Fu,
     Fu: Future<Output = ()>,
@@ -54,7 +56,9 @@ where
                         if s.success() {
                             on_success().await;
                             if delete {
-                                fs::remove_file(src).await.unwrap();
+                                if let Err(e) = fs::remove_file(src).await {
+                                    PROGRESS.eprintln(format!("unable to remove {src}: {e:?}"));
+                                }
                             }
                             return true;
                         } else {
@@ -63,28 +67,34 @@ where
                                     kind: RcloneErrorKind::MaxRetriesExceeded,
                                     msg: format!("max retries exceeded for '{}'", src_str),
                                 });*/
-                                eprintln!("giving up on uploading {}", src);
+                                PROGRESS.eprintln(format!("giving up on uploading {}", src));
                                 return false;
                             }
                             let mut err: String = String::new();
-                            _stderr.read_to_string(&mut err).await.unwrap();
-                            eprintln!("upload error: {}", err);
+                            _stderr.read_to_string(&mut err).await.ok();
+                            PROGRESS.eprintln(format!("upload error: {}", err));
                             errors += 1;
                         }
                     }
-                    Err(e) => panic!("{:?}", e),
+                    Err(e) => {
+                        PROGRESS.eprintln(format!("rclone failed for {src}: {e:?}"));
+                        return false;
+                    }
                 }
             }
             Err(e) => match e.kind() {
                 io::ErrorKind::NotFound => {
-                    eprintln!("do you have rclone installed?");
+                    PROGRESS.eprintln("do you have rclone installed?");
                     exit(1);
                 }
                 io::ErrorKind::PermissionDenied => {
-                    eprintln!("unable to execute rclone!");
+                    PROGRESS.eprintln("unable to execute rclone!");
                     exit(1);
                 }
-                _ => panic!("{:?}", e),
+                _ => {
+                    PROGRESS.eprintln(format!("unable to execute rclone: {e:?}"));
+                    return false;
+                }
             },
         };
     }
@@ -95,6 +105,7 @@ where
     F: FnOnce() -> Fu,
     Fu: Future<Output = ()>,
 {
+    let mut errors: u8 = 0;
     loop {
         match Command::new("rclone")
             .args([
@@ -115,20 +126,58 @@ where
                 match child.wait().await {
                     Ok(exit) => {
                         if exit.success() {
-                            println!("successfully moved {src} to {dest}");
+                            PROGRESS.println(format!("successfully moved {src} to {dest}"));
                             on_success().await;
                             return true;
                         } else {
                             let mut err = String::new();
-                            _stderr.read_to_string(&mut err).await.unwrap();
-                            stderr().write_all(err.as_bytes()).await.unwrap();
-                            stderr().flush().await.unwrap();
+                            _stderr.read_to_string(&mut err).await.ok();
+                            PROGRESS.eprintln(err.trim_end());
+                            if errors > 5 {
+                                PROGRESS.eprintln(format!("giving up on moving {src}"));
+                                return false;
+                            }
+                            errors += 1;
                         }
                     }
-                    Err(e) => panic!("{:?}", e),
+                    Err(e) => {
+                        PROGRESS.eprintln(format!("rclone failed for {src}: {e:?}"));
+                        return false;
+                    }
                 }
             }
-            Err(e) => panic!("{:?}", e),
+            Err(e) => {
+                PROGRESS.eprintln(format!("unable to execute rclone: {e:?}"));
+                return false;
+            }
+        }
+    }
+}
+
+/// size of a remote file, None if it doesn't exist
+pub async fn size(remote: &str) -> Result<Option<u64>, crate::Error> {
+    // exit codes of `rclone lsjson` for a missing directory or file
+    const NOT_FOUND: [i32; 2] = [3, 4];
+
+    let output = Command::new("rclone")
+        .args(["lsjson", "--stat", "--no-mimetype", "--no-modtime", remote])
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| crate::Error::Rclone(format!("unable to execute rclone: {e}")))?;
+    if !output.status.success() {
+        if output.status.code().is_some_and(|c| NOT_FOUND.contains(&c)) {
+            return Ok(None);
         }
+        return Err(crate::Error::Rclone(format!(
+            "{remote}: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let stat: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    match stat["Size"].as_i64() {
+        // directories have a size of -1
+        Some(size) if size >= 0 => return Ok(Some(size as u64)),
+        _ => return Ok(None),
     }
 }
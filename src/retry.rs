@@ -0,0 +1,214 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    time::SystemTime,
+};
+
+// crate
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use tokio::time::{sleep, Duration};
+
+// local
+use crate::statics::PROGRESS;
+
+/// shared retry policy for api requests and downloads.
+/// a negative `retries` value means retrying forever.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub retries: i64,
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: i64, base: Duration, max: Duration) -> Self {
+        Self { retries, base, max }
+    }
+
+    pub fn backoff(&self) -> Backoff {
+        Backoff {
+            policy: *self,
+            attempts: 0,
+        }
+    }
+
+    /// exponential backoff with jitter, `attempt` starts from 1.
+    /// the result is in range of [delay / 2, delay] where delay is capped at `max`.
+    pub fn delay(&self, attempt: i64) -> Duration {
+        let exp = attempt.saturating_sub(1).clamp(0, 31) as u32;
+        let delay = self
+            .base
+            .saturating_mul(2u32.saturating_pow(exp))
+            .min(self.max);
+        let half = delay / 2;
+        return half + half.mul_f64(jitter());
+    }
+}
+
+/// keeps track of attempts for a single request.
+pub struct Backoff {
+    policy: RetryPolicy,
+    attempts: i64,
+}
+
+impl Backoff {
+    pub fn attempts(&self) -> i64 {
+        self.attempts
+    }
+
+    /// registers a failed attempt and sleeps before the next one.
+    /// `retry_after` is respected over the computed delay when it's longer.
+    pub async fn wait<R: fmt::Display>(
+        &mut self,
+        reason: R,
+        retry_after: Option<Duration>,
+    ) -> Result<(), RetryError> {
+        self.attempts += 1;
+        if self.policy.retries >= 0 && self.attempts > self.policy.retries {
+            return Err(RetryError {
+                attempts: self.attempts,
+                reason: reason.to_string(),
+            });
+        }
+        let delay = match retry_after {
+            Some(after) => after.max(self.policy.delay(self.attempts)),
+            None => self.policy.delay(self.attempts),
+        };
+        let retries = match self.policy.retries {
+            r if r < 0 => "∞".to_string(),
+            r => r.to_string(),
+        };
+        PROGRESS.eprintln(format!(
+            "{reason}, retrying in {:.1}s ({}/{retries})",
+            delay.as_secs_f32(),
+            self.attempts,
+        ));
+        sleep(delay).await;
+        return Ok(());
+    }
+}
+
+#[derive(Debug)]
+pub struct RetryError {
+    pub attempts: i64,
+    pub reason: String,
+}
+
+impl fmt::Display for RetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "giving up after {} attempts, last error: {}",
+            self.attempts, self.reason
+        )
+    }
+}
+
+impl std::error::Error for RetryError {}
+
+/// status codes that ask us to slow down
+pub fn is_throttled(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+    )
+}
+
+/// parses `Retry-After` as either delay-seconds or an http-date.
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => match httpdate::parse_http_date(value) {
+            Ok(date) => Some(
+                date.duration_since(SystemTime::now())
+                    .unwrap_or(Duration::ZERO),
+            ),
+            Err(_) => None,
+        },
+    }
+}
+
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    return (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn delay_stays_within_bounds() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(1), Duration::from_secs(10));
+        let bounds = [
+            (1, 1),
+            (2, 2),
+            (3, 4),
+            (4, 8),
+            (5, 10),
+            (40, 10),
+            (i64::MAX, 10),
+        ];
+        for (attempt, secs) in bounds {
+            let delay = Duration::from_secs(secs);
+            for _ in 0..100 {
+                let d = policy.delay(attempt);
+                assert!(d >= delay / 2 && d <= delay, "attempt {attempt}: {d:?}");
+            }
+        }
+        // attempts before the first are treated as the first
+        assert!(policy.delay(0) <= Duration::from_secs(1));
+        assert!(policy.delay(-3) <= Duration::from_secs(1));
+    }
+
+    #[test]
+    fn delay_is_jittered() {
+        let policy = RetryPolicy::new(5, Duration::from_secs(8), Duration::from_secs(8));
+        let delays: Vec<Duration> = (0..100).map(|_| policy.delay(1)).collect();
+        assert!(delays.iter().any(|d| *d != delays[0]));
+        for _ in 0..1000 {
+            let j = jitter();
+            assert!((0.0..1.0).contains(&j));
+        }
+    }
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        return headers;
+    }
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(retry_after(&headers("120")), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(&headers(" 0 ")), Some(Duration::ZERO));
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+        assert_eq!(retry_after(&headers("-1")), None);
+        assert_eq!(retry_after(&headers("soon")), None);
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let later = SystemTime::now() + Duration::from_secs(300);
+        let delay = retry_after(&headers(&httpdate::fmt_http_date(later))).unwrap();
+        // the date only has whole seconds
+        assert!(delay > Duration::from_secs(298) && delay <= Duration::from_secs(300));
+        // dates in the past mean right away
+        assert_eq!(
+            retry_after(&headers("Sun, 06 Nov 1994 08:49:37 GMT")),
+            Some(Duration::ZERO)
+        );
+    }
+}
@@ -0,0 +1,461 @@
+//! versioned database schema, `PRAGMA user_version` is the number of applied migrations.
+//! new migrations are appended to `MIGRATIONS`, applied ones must never change.
+
+use std::path::{Path, PathBuf};
+
+// crate
+use sqlite::{Connection, State};
+
+// local
+use crate::{
+    platforms::statics::{KONACHAN, SAKUGABOORU, YANDERE},
+    Config, Error,
+};
+
+type Migration = fn(&Connection) -> Result<(), Error>;
+
+/// platform tables with the schema of their database, e.g. `main` or `db1`
+pub(crate) type Tables = Vec<(String, &'static str)>;
+
+const MIGRATIONS: &[Migration] = &[
+    v1_baseline,
+    v2_tags,
+    v3_metadata,
+    v4_fts,
+    v5_crossref,
+    v6_dhash,
+    v7_imported,
+];
+
+pub(crate) fn platform_tables() -> [&'static str; 3] {
+    [YANDERE, KONACHAN, SAKUGABOORU]
+}
+
+/// full text index of a platform table, its rowid is the post id
+pub(crate) fn fts_table(table: &str) -> String {
+    format!("{table}_fts")
+}
+
+/// tags go into the full text index as one token each, every byte that isn't an ascii
+/// letter or digit is written as `_` and its hex value. otherwise the tokenizer would split
+/// `long_hair` into `long` and `hair`, which also matches the tags `long` and `hair`.
+/// the encoding keeps prefixes so `tag*` queries still work.
+pub(crate) fn fts_token(tag: &str) -> String {
+    let mut ret = String::with_capacity(tag.len());
+    for byte in tag.bytes() {
+        match byte.is_ascii_alphanumeric() {
+            true => ret.push(byte.to_ascii_lowercase() as char),
+            false => ret.push_str(format!("_{byte:02x}").as_str()),
+        }
+    }
+    return ret;
+}
+
+pub(crate) fn fts_tags(tags: &str) -> String {
+    tags.split_whitespace()
+        .map(fts_token)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// opens the databases of all platforms as one connection, the first one is `main` and the
+/// others are attached as `db1`, `db2`, ... returns every platform table with its schema.
+pub(crate) fn open(config: &Config) -> Result<(Connection, Tables), Error> {
+    let databases = config.databases();
+    let path = match databases.first() {
+        Some((path, _)) => path.clone(),
+        None => return Err(Error::Schema("no database".to_string())),
+    };
+    for (path, _) in databases.iter() {
+        migrate(&Connection::open(path)?)?;
+    }
+    let main = Connection::open(&path)?;
+    let tables = attach(&main, &path, databases)?;
+    return Ok((main, tables));
+}
+
+/// attaches `databases` to the connection of `database` as `db1`, `db2`, ...
+/// `database` itself is `main`. returns every platform table with its schema.
+pub(crate) fn attach(
+    connection: &Connection,
+    database: &Path,
+    databases: Vec<(PathBuf, Vec<&'static str>)>,
+) -> Result<Tables, Error> {
+    let mut tables: Tables = Vec::new();
+    for (i, (path, platforms)) in databases.into_iter().enumerate() {
+        let schema = match path == database {
+            true => "main".to_string(),
+            false => {
+                let schema = format!("db{i}");
+                let mut statement = connection.prepare(format!("ATTACH DATABASE ? AS {schema}"))?;
+                statement.bind((1, path.to_string_lossy().as_ref()))?;
+                while let State::Row = statement.next()? {}
+                schema
+            }
+        };
+        tables.extend(platforms.into_iter().map(|t| (schema.clone(), t)));
+    }
+    return Ok(tables);
+}
+
+/// brings the database up to the latest schema, each migration runs in its own transaction.
+pub fn migrate(connection: &Connection) -> Result<(), Error> {
+    if !has_fts5(connection)? {
+        return Err(Error::Schema(
+            "sqlite was built without fts5, which the search index needs. \
+            rebuild with the SQLITE_ENABLE_FTS5=1 env var"
+                .to_string(),
+        ));
+    }
+    let version = user_version(connection)?;
+    if version > MIGRATIONS.len() {
+        return Err(Error::Schema(format!(
+            "database schema version {version} is newer than the supported version {}",
+            MIGRATIONS.len()
+        )));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        connection.execute("BEGIN")?;
+        let result = migration(connection)
+            .and_then(|_| Ok(connection.execute(format!("PRAGMA user_version = {}", i + 1))?));
+        match result {
+            Ok(_) => connection.execute("COMMIT")?,
+            Err(e) => {
+                connection.execute("ROLLBACK").ok();
+                return Err(Error::Schema(format!(
+                    "migration to version {}: {e}",
+                    i + 1
+                )));
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// the bundled sqlite only has fts5 when it's built with `SQLITE_ENABLE_FTS5`, which
+/// `.cargo/config.toml` sets inside this workspace only
+fn has_fts5(connection: &Connection) -> Result<bool, Error> {
+    let mut statement = connection.prepare("SELECT sqlite_compileoption_used('ENABLE_FTS5')")?;
+    let mut ret: i64 = 0;
+    while let State::Row = statement.next()? {
+        ret = statement.read::<i64, _>(0)?;
+    }
+    return Ok(ret == 1);
+}
+
+fn user_version(connection: &Connection) -> Result<usize, Error> {
+    let mut statement = connection.prepare("PRAGMA user_version")?;
+    let mut ret: i64 = 0;
+    while let State::Row = statement.next()? {
+        ret = statement.read::<i64, _>(0)?;
+    }
+    return Ok(ret as usize);
+}
+
+/// tables that existed before versioning, per platform tables used to be created on the
+/// first insert with the same six columns so existing ones are kept as they are.
+fn v1_baseline(connection: &Connection) -> Result<(), Error> {
+    for table in platform_tables() {
+        connection.execute(format!(
+            "CREATE TABLE IF NOT EXISTS {table}(
+                id INT PRIMARY KEY,
+                md5 TEXT NOT NULL,
+                source TEXT,
+                tags TEXT,
+                path TEXT NOT NULL,
+                compress_path TEXT)"
+        ))?;
+    }
+    connection.execute(
+        "CREATE TABLE IF NOT EXISTS failed(
+            platform TEXT NOT NULL,
+            id INT NOT NULL,
+            url TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            attempts INT NOT NULL,
+            last_attempt INT NOT NULL,
+            PRIMARY KEY (platform, id));
+        CREATE TABLE IF NOT EXISTS queue(
+            platform TEXT NOT NULL,
+            id INT NOT NULL,
+            post TEXT NOT NULL,
+            claimed INT NOT NULL DEFAULT 0,
+            PRIMARY KEY (platform, id))",
+    )?;
+    return Ok(());
+}
+
+/// normalized tags, `post_tags.tag` also decides the platform of `post_tags.post`.
+/// existing posts are linked to their tags without a type, it's filled in when they're saved again.
+fn v2_tags(connection: &Connection) -> Result<(), Error> {
+    connection.execute(
+        "CREATE TABLE tags(
+            id INTEGER PRIMARY KEY,
+            platform TEXT NOT NULL,
+            name TEXT NOT NULL,
+            type TEXT,
+            UNIQUE (platform, name));
+        CREATE TABLE post_tags(
+            post INT NOT NULL,
+            tag INT NOT NULL REFERENCES tags(id),
+            PRIMARY KEY (post, tag));
+        CREATE INDEX post_tags_tag ON post_tags(tag)",
+    )?;
+    for table in platform_tables() {
+        let mut rows = connection.prepare(format!(
+            "SELECT id, tags FROM {table} WHERE tags IS NOT NULL"
+        ))?;
+        let mut insert_tag = connection.prepare(
+            "INSERT INTO tags(platform, name) VALUES(?, ?) ON CONFLICT(platform, name) DO NOTHING",
+        )?;
+        let mut insert_post_tag = connection.prepare(
+            "INSERT OR IGNORE INTO post_tags
+                SELECT ?, id FROM tags WHERE platform = ? AND name = ?",
+        )?;
+        while let State::Row = rows.next()? {
+            let id = rows.read::<i64, _>(0)?;
+            let tags = rows.read::<String, _>(1)?;
+            for name in tags.split_whitespace() {
+                insert_tag.reset()?;
+                insert_tag.bind((1, table))?;
+                insert_tag.bind((2, name))?;
+                while let State::Row = insert_tag.next()? {}
+                insert_post_tag.reset()?;
+                insert_post_tag.bind((1, id))?;
+                insert_post_tag.bind((2, table))?;
+                insert_post_tag.bind((3, name))?;
+                while let State::Row = insert_post_tag.next()? {}
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// post metadata, NULL for posts saved before this version until they're saved again
+fn v3_metadata(connection: &Connection) -> Result<(), Error> {
+    const COLUMNS: [&str; 12] = [
+        "rating TEXT",
+        "score INT",
+        "width INT",
+        "height INT",
+        "file_size INT",
+        "file_ext TEXT",
+        "created_at INT",
+        "updated_at INT",
+        "author TEXT",
+        "parent_id INT",
+        "status TEXT",
+        "file_url TEXT",
+    ];
+    for table in platform_tables() {
+        for column in COLUMNS {
+            connection.execute(format!("ALTER TABLE {table} ADD COLUMN {column}"))?;
+        }
+    }
+    return Ok(());
+}
+
+/// full text index over tags and source, kept in sync by `Worker::insert`
+fn v4_fts(connection: &Connection) -> Result<(), Error> {
+    for table in platform_tables() {
+        let fts = fts_table(table);
+        connection.execute(format!(
+            "CREATE VIRTUAL TABLE {fts} USING fts5(tags, source, tokenize = \"ascii tokenchars '_'\")"
+        ))?;
+        let mut rows = connection.prepare(format!("SELECT id, tags, source FROM {table}"))?;
+        let mut insert = connection.prepare(format!(
+            "INSERT INTO {fts}(rowid, tags, source) VALUES(?, ?, ?)"
+        ))?;
+        while let State::Row = rows.next()? {
+            let tags = rows.read::<Option<String>, _>(1)?.unwrap_or_default();
+            insert.reset()?;
+            insert.bind((1, rows.read::<i64, _>(0)?))?;
+            insert.bind((2, fts_tags(tags.as_str()).as_str()))?;
+            insert.bind((
+                3,
+                rows.read::<Option<String>, _>(2)?
+                    .unwrap_or_default()
+                    .as_str(),
+            ))?;
+            while let State::Row = insert.next()? {}
+        }
+    }
+    return Ok(());
+}
+
+/// md5 index for finding a file across platforms, `crossref` records the posts that were
+/// found on another platform and what was done with them
+fn v5_crossref(connection: &Connection) -> Result<(), Error> {
+    for table in platform_tables() {
+        connection.execute(format!("CREATE INDEX {table}_md5 ON {table}(md5)"))?;
+    }
+    connection.execute(
+        "CREATE TABLE crossref(
+            platform TEXT NOT NULL,
+            id INT NOT NULL,
+            md5 TEXT NOT NULL,
+            other_platform TEXT NOT NULL,
+            other_id INT NOT NULL,
+            action TEXT NOT NULL,
+            PRIMARY KEY (platform, id));
+        CREATE INDEX crossref_other ON crossref(other_platform, other_id)",
+    )?;
+    return Ok(());
+}
+
+/// perceptual hash of saved images, NULL for other files and images saved before this version
+fn v6_dhash(connection: &Connection) -> Result<(), Error> {
+    for table in platform_tables() {
+        connection.execute(format!("ALTER TABLE {table} ADD COLUMN dhash INT"))?;
+    }
+    return Ok(());
+}
+
+/// posts imported without --move, their files stay where they were found
+fn v7_imported(connection: &Connection) -> Result<(), Error> {
+    connection.execute(
+        "CREATE TABLE imported(
+            platform TEXT NOT NULL,
+            id INT NOT NULL,
+            PRIMARY KEY (platform, id))",
+    )?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fts_token_keeps_tags_whole() {
+        assert_eq!(fts_token("long_hair"), "long_5fhair");
+        assert_eq!(fts_token("Blue"), "blue");
+        assert_eq!(fts_token("k-on!"), "k_2don_21");
+        assert_eq!(fts_token(":)"), "_3a_29");
+        // every byte of a multibyte character
+        assert_eq!(fts_token("é"), "_c3_a9");
+        assert_eq!(fts_tags("  long_hair   hair "), "long_5fhair hair");
+    }
+
+    #[test]
+    fn fts_token_keeps_prefixes() {
+        assert!(fts_token("long_hair").starts_with(&fts_token("long_")));
+        assert!(fts_token("long_hair").starts_with(&fts_token("long")));
+        assert!(!fts_token("hair").starts_with(&fts_token("long_hair")));
+    }
+
+    #[test]
+    fn fts_matches_whole_tags() {
+        let connection = Connection::open(":memory:").unwrap();
+        migrate(&connection).unwrap();
+        connection
+            .execute(format!(
+                "INSERT INTO yandere_fts(rowid, tags, source)
+                    VALUES(1, '{}', ''), (2, '{}', '')",
+                fts_tags("long_hair k-on!"),
+                fts_tags("long hair"),
+            ))
+            .unwrap();
+        let search = |token: String| -> Vec<i64> {
+            let mut statement = connection
+                .prepare("SELECT rowid FROM yandere_fts WHERE yandere_fts MATCH ? ORDER BY rowid")
+                .unwrap();
+            statement
+                .bind((1, format!("tags : {token}").as_str()))
+                .unwrap();
+            let mut ret = Vec::new();
+            while let State::Row = statement.next().unwrap() {
+                ret.push(statement.read::<i64, _>(0).unwrap());
+            }
+            return ret;
+        };
+        assert_eq!(search(fts_token("long_hair")), [1]);
+        assert_eq!(search(fts_token("hair")), [2]);
+        assert_eq!(search(fts_token("k-on!")), [1]);
+        assert_eq!(search(format!("{}*", fts_token("long"))), [1, 2]);
+        assert_eq!(search(format!("{}*", fts_token("long_"))), [1]);
+    }
+
+    /// first column of every row, as text
+    fn rows(connection: &Connection, query: &str) -> Vec<String> {
+        let mut statement = connection.prepare(query).unwrap();
+        let mut ret = Vec::new();
+        while let State::Row = statement.next().unwrap() {
+            ret.push(
+                statement
+                    .read::<Option<String>, _>(0)
+                    .unwrap()
+                    .unwrap_or_default(),
+            );
+        }
+        return ret;
+    }
+
+    #[test]
+    fn migrate_legacy_table() {
+        let connection = Connection::open(":memory:").unwrap();
+        connection
+            .execute(
+                "CREATE TABLE yandere(
+                    id INT PRIMARY KEY,
+                    md5 TEXT NOT NULL,
+                    source TEXT,
+                    tags TEXT,
+                    path TEXT NOT NULL,
+                    compress_path TEXT);
+                INSERT INTO yandere VALUES
+                    (1, 'a', 'https://example.com/1', 'long_hair k-on!', '/y/1.jpg', NULL),
+                    (2, 'b', NULL, NULL, '/y/2.png', '/y/2.webp')",
+            )
+            .unwrap();
+        migrate(&connection).unwrap();
+        assert_eq!(user_version(&connection).unwrap(), MIGRATIONS.len());
+        // running it again changes nothing
+        migrate(&connection).unwrap();
+        assert_eq!(user_version(&connection).unwrap(), MIGRATIONS.len());
+
+        let columns = rows(&connection, "SELECT name FROM pragma_table_info('yandere')");
+        let legacy = ["id", "md5", "source", "tags", "path", "compress_path"];
+        assert_eq!(columns[..6], legacy);
+        for column in ["rating", "score", "file_size", "file_url", "dhash"] {
+            assert!(columns.iter().any(|c| c == column), "{column}");
+        }
+        assert_eq!(
+            rows(
+                &connection,
+                "SELECT path || ' ' || ifnull(rating, 'none') FROM yandere ORDER BY id"
+            ),
+            ["/y/1.jpg none", "/y/2.png none"]
+        );
+
+        assert_eq!(
+            rows(
+                &connection,
+                "SELECT tags.name FROM post_tags JOIN tags ON tags.id = post_tags.tag
+                    WHERE post_tags.post = 1 AND tags.platform = 'yandere' AND tags.type IS NULL
+                    ORDER BY tags.name"
+            ),
+            ["k-on!", "long_hair"]
+        );
+        assert_eq!(
+            rows(&connection, "SELECT COUNT(*) FROM post_tags WHERE post = 2"),
+            ["0"]
+        );
+
+        assert_eq!(
+            rows(
+                &connection,
+                "SELECT rowid || ' ' || tags || ' ' || source FROM yandere_fts ORDER BY rowid"
+            ),
+            [
+                format!("1 {} https://example.com/1", fts_tags("long_hair k-on!")),
+                "2  ".to_string()
+            ]
+        );
+        // the other platforms get empty tables
+        assert_eq!(
+            rows(&connection, "SELECT COUNT(*) FROM konachan_fts"),
+            ["0"]
+        );
+    }
+}
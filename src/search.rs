@@ -0,0 +1,443 @@
+//! offline search over the archive database with booru style queries, e.g.
+//! `tag1 -tag2 rating:s score:>50 width:>=2000 platform:yandere order:score`
+
+use std::collections::BTreeMap;
+
+// crate
+use serde::Serialize;
+use sqlite::{Connection, State, Value};
+
+// local
+use crate::{
+    schema,
+    utils::parse_size,
+    worker::{read_entry, DbEntry, COLUMNS},
+    Config, Error,
+};
+
+pub struct Query {
+    tables: schema::Tables,
+    conditions: Vec<String>,
+    params: Vec<Value>,
+    order: &'static str,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResult {
+    pub platform: String,
+    /// `path` with the rclone remote in front when the platform uploads to the cloud
+    pub location: String,
+    #[serde(flatten)]
+    pub entry: DbEntry,
+}
+
+impl Query {
+    pub fn parse<S: AsRef<str>>(terms: &[S], tables: schema::Tables) -> Result<Self, Error> {
+        let mut query = Query {
+            tables,
+            conditions: Vec::new(),
+            params: Vec::new(),
+            order: "id DESC",
+            limit: None,
+        };
+        for term in terms.iter().flat_map(|t| t.as_ref().split_whitespace()) {
+            let (negate, term) = match term.strip_prefix('-') {
+                Some(t) if !t.is_empty() => (true, t),
+                _ => (false, term),
+            };
+            let condition = match term.split_once(':') {
+                Some((key, value)) if !value.is_empty() => match key {
+                    "order" if !negate => {
+                        query.order = order(value)?;
+                        continue;
+                    }
+                    "limit" if !negate => {
+                        query.limit = Some(integer(value)?);
+                        continue;
+                    }
+                    // tags like `re:zero` or `:d`, booru sites search them as tags too
+                    _ => match query.meta(key, value)? {
+                        Some(condition) => condition,
+                        None => query.tag(term),
+                    },
+                },
+                _ => query.tag(term),
+            };
+            query.conditions.push(match negate {
+                true => format!("NOT ({condition})"),
+                false => condition,
+            });
+        }
+        return Ok(query);
+    }
+
+    /// adds a parameter, returns its placeholder
+    fn param(&mut self, value: Value) -> String {
+        self.params.push(value);
+        return format!("?{}", self.params.len());
+    }
+
+    /// `condition` in the database of the post's platform, `{schema}` is replaced
+    /// with the schema of the database
+    fn per_platform(&self, condition: &str) -> String {
+        let platforms = self
+            .tables
+            .iter()
+            .map(|(schema, table)| {
+                format!(
+                    "(p.platform = '{table}' AND {})",
+                    condition
+                        .replace("{schema}", schema)
+                        .replace("{table}", table)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" OR ");
+        return format!("({platforms})");
+    }
+
+    /// full text match against the index of the post's platform
+    fn fts(&mut self, expression: String) -> String {
+        let expression = self.param(Value::String(expression));
+        return self.per_platform(
+            format!(
+                "p.id IN (SELECT rowid FROM {{schema}}.{fts} WHERE {fts} MATCH {expression})",
+                fts = schema::fts_table("{table}")
+            )
+            .as_str(),
+        );
+    }
+
+    /// `tag` and `tag*` use the full text index, other wildcards fall back to `GLOB`
+    fn tag(&mut self, name: &str) -> String {
+        match name.find('*') {
+            None => return self.fts(format!("tags : {}", schema::fts_token(name))),
+            Some(i) if i > 0 && i == name.len() - 1 => {
+                return self.fts(format!("tags : {}*", schema::fts_token(&name[..i])))
+            }
+            _ => {
+                let name = self.param(Value::String(name.to_string()));
+                return self.per_platform(
+                    format!(
+                        "EXISTS (SELECT 1 FROM {{schema}}.post_tags
+                            JOIN {{schema}}.tags ON tags.id = post_tags.tag
+                            WHERE post_tags.post = p.id AND tags.platform = p.platform
+                            AND tags.name GLOB {name})"
+                    )
+                    .as_str(),
+                );
+            }
+        }
+    }
+
+    fn meta(&mut self, key: &str, value: &str) -> Result<Option<String>, Error> {
+        let text = |column: &str, query: &mut Self, value: String| -> String {
+            format!("{column} = {}", query.param(Value::String(value)))
+        };
+        match key {
+            "rating" => {
+                // s, q, e or safe, questionable, explicit
+                let rating = value.chars().take(1).collect::<String>().to_lowercase();
+                return Ok(Some(text("rating", self, rating)));
+            }
+            "platform" => return Ok(Some(text("platform", self, value.to_string()))),
+            "md5" => return Ok(Some(text("md5", self, value.to_ascii_lowercase()))),
+            "ext" | "file_ext" => return Ok(Some(text("file_ext", self, value.to_string()))),
+            "status" => return Ok(Some(text("status", self, value.to_string()))),
+            "author" | "user" => return Ok(Some(text("author", self, value.to_string()))),
+            // words of the source as a phrase, e.g. `source:twitter.com/artist`
+            "source" => {
+                let phrase = format!("source : \"{}\"", value.replace('"', "\"\""));
+                return Ok(Some(self.fts(phrase)));
+            }
+            "id" => return self.range("id", value, integer).map(Some),
+            "score" => return self.range("score", value, integer).map(Some),
+            "width" => return self.range("width", value, integer).map(Some),
+            "height" => return self.range("height", value, integer).map(Some),
+            "parent" => return self.range("parent_id", value, integer).map(Some),
+            "filesize" | "file_size" => return self.range("file_size", value, size).map(Some),
+            // not a metatag
+            _ => return Ok(None),
+        }
+    }
+
+    /// `value`, `>value`, `>=value`, `<value`, `<=value` or `min..max`
+    fn range(
+        &mut self,
+        column: &str,
+        value: &str,
+        parse: fn(&str) -> Result<i64, Error>,
+    ) -> Result<String, Error> {
+        if let Some((min, max)) = value.split_once("..") {
+            let min = self.param(Value::Integer(parse(min)?));
+            let max = self.param(Value::Integer(parse(max)?));
+            return Ok(format!("{column} BETWEEN {min} AND {max}"));
+        }
+        let (op, value) = [">=", "<=", ">", "<"]
+            .into_iter()
+            .find_map(|op| value.strip_prefix(op).map(|v| (op, v)))
+            .unwrap_or(("=", value));
+        let value = self.param(Value::Integer(parse(value)?));
+        return Ok(format!("{column} {op} {value}"));
+    }
+
+    /// runs the query against every platform table
+    pub fn execute(&self, connection: &Connection) -> Result<Vec<(String, DbEntry)>, Error> {
+        let union = self
+            .tables
+            .iter()
+            .map(|(schema, table)| format!("SELECT '{table}' AS platform, * FROM {schema}.{table}"))
+            .collect::<Vec<String>>()
+            .join(" UNION ALL ");
+        let mut sql = format!("SELECT {COLUMNS}, platform FROM ({union}) AS p");
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(self.conditions.join(" AND ").as_str());
+        }
+        sql.push_str(format!(" ORDER BY {}", self.order).as_str());
+        if let Some(limit) = self.limit {
+            sql.push_str(format!(" LIMIT {limit}").as_str());
+        }
+        let mut statement = connection.prepare(sql)?;
+        for (i, param) in self.params.iter().enumerate() {
+            statement.bind((i + 1, param.clone()))?;
+        }
+        let mut ret: Vec<(String, DbEntry)> = Vec::new();
+        while let State::Row = statement.next()? {
+            let platform = statement.read::<String, _>("platform")?;
+            ret.push((platform, read_entry(&statement)?));
+        }
+        return Ok(ret);
+    }
+}
+
+fn order(value: &str) -> Result<&'static str, Error> {
+    match value {
+        "id" => Ok("id DESC"),
+        "id_asc" => Ok("id ASC"),
+        "score" => Ok("score DESC"),
+        "score_asc" => Ok("score ASC"),
+        "date" => Ok("created_at DESC"),
+        "date_asc" => Ok("created_at ASC"),
+        "filesize" => Ok("file_size DESC"),
+        "filesize_asc" => Ok("file_size ASC"),
+        "random" => Ok("RANDOM()"),
+        _ => Err(Error::Query(format!("unknown order: {value}"))),
+    }
+}
+
+fn integer(value: &str) -> Result<i64, Error> {
+    value
+        .parse::<i64>()
+        .map_err(|_| Error::Query(format!("not a number: {value}")))
+}
+
+fn size(value: &str) -> Result<i64, Error> {
+    match parse_size(value) {
+        Some(n) => Ok(n as i64),
+        None => Err(Error::Query(format!("not a size: {value}"))),
+    }
+}
+
+/// the rclone remote of every platform that uploads to the cloud
+pub(crate) fn clouds(config: &Config) -> BTreeMap<&str, Option<&str>> {
+    config
+        .platforms
+        .iter()
+        .map(|p| {
+            let c = p.config();
+            (p.name(), c.to_cloud.then_some(c.cloud.as_str()))
+        })
+        .collect()
+}
+
+impl SearchResult {
+    pub(crate) fn new(
+        clouds: &BTreeMap<&str, Option<&str>>,
+        platform: String,
+        entry: DbEntry,
+    ) -> Self {
+        let location = match clouds.get(platform.as_str()) {
+            Some(Some(cloud)) => format!("{cloud}:{}", entry.path),
+            _ => entry.path.clone(),
+        };
+        return SearchResult {
+            platform,
+            location,
+            entry,
+        };
+    }
+}
+
+/// the `search` command, prints one path per line or a json array with `json`
+pub fn run<S: AsRef<str>>(config: &Config, terms: &[S], json: bool) -> Result<(), Error> {
+    let (connection, tables) = schema::open(config)?;
+    let query = Query::parse(terms, tables)?;
+    let clouds = clouds(config);
+    let results: Vec<SearchResult> = query
+        .execute(&connection)?
+        .into_iter()
+        .map(|(platform, entry)| SearchResult::new(&clouds, platform, entry))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for result in results {
+            println!("{}", result.location);
+        }
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables() -> schema::Tables {
+        schema::platform_tables()
+            .into_iter()
+            .map(|t| ("main".to_string(), t))
+            .collect()
+    }
+
+    fn parse(terms: &str) -> Result<Query, Error> {
+        Query::parse(&[terms], tables())
+    }
+
+    /// an archive with posts of yandere and konachan
+    fn archive() -> Connection {
+        let connection = Connection::open(":memory:").unwrap();
+        schema::migrate(&connection).unwrap();
+        for (table, id, tags, rating, score) in [
+            ("yandere", 1, "long_hair blue_eyes", "s", 10),
+            ("yandere", 2, "long_hair", "e", 60),
+            ("yandere", 3, "short_hair blue_eyes", "s", 80),
+            ("konachan", 1, "long hair", "q", 5),
+            (
+                "konachan",
+                2,
+                "re:zero_kara_hajimeru_isekai_seikatsu :d",
+                "q",
+                0,
+            ),
+        ] {
+            connection
+                .execute(format!(
+                    "INSERT INTO {table}(id, md5, tags, path, rating, score)
+                        VALUES({id}, 'md5{id}', '{tags}', '/{table}/{id}', '{rating}', {score});
+                    INSERT INTO {fts}(rowid, tags, source) VALUES({id}, '{fts_tags}', '')",
+                    fts = schema::fts_table(table),
+                    fts_tags = schema::fts_tags(tags),
+                ))
+                .unwrap();
+            for tag in tags.split_whitespace() {
+                connection
+                    .execute(format!(
+                        "INSERT OR IGNORE INTO tags(platform, name) VALUES('{table}', '{tag}');
+                        INSERT INTO post_tags SELECT {id}, id FROM tags
+                            WHERE platform = '{table}' AND name = '{tag}'"
+                    ))
+                    .unwrap();
+            }
+        }
+        return connection;
+    }
+
+    fn search(terms: &str) -> Vec<String> {
+        parse(terms)
+            .unwrap()
+            .execute(&archive())
+            .unwrap()
+            .into_iter()
+            .map(|(platform, entry)| format!("{platform} {}", entry.id))
+            .collect()
+    }
+
+    #[test]
+    fn parse_metatags() {
+        let query = parse("rating:safe score:>50 width:100..200 -md5:ABC").unwrap();
+        assert_eq!(
+            query.conditions,
+            [
+                "rating = ?1",
+                "score > ?2",
+                "width BETWEEN ?3 AND ?4",
+                "NOT (md5 = ?5)"
+            ]
+        );
+        assert_eq!(
+            query.params,
+            [
+                Value::String("s".to_string()),
+                Value::Integer(50),
+                Value::Integer(100),
+                Value::Integer(200),
+                Value::String("abc".to_string()),
+            ]
+        );
+        let query = parse("order:score_asc limit:5 filesize:<=1KiB").unwrap();
+        assert_eq!(query.order, "score ASC");
+        assert_eq!(query.limit, Some(5));
+        assert_eq!(query.conditions, ["file_size <= ?1"]);
+        assert_eq!(query.params, [Value::Integer(1024)]);
+    }
+
+    #[test]
+    fn parse_tags() {
+        let query = parse("long_hair blue* *eyes").unwrap();
+        assert_eq!(
+            query.params,
+            [
+                Value::String("tags : long_5fhair".to_string()),
+                Value::String("tags : blue*".to_string()),
+                Value::String("*eyes".to_string()),
+            ]
+        );
+        assert!(query.conditions[0].contains("yandere_fts MATCH ?1"));
+        assert!(query.conditions[2].contains("tags.name GLOB ?3"));
+    }
+
+    #[test]
+    fn parse_tags_with_colons() {
+        let query = parse("re:zero_kara_hajimeru_isekai_seikatsu :d -foo:bar").unwrap();
+        assert_eq!(
+            query.params,
+            [
+                Value::String(format!(
+                    "tags : {}",
+                    schema::fts_token("re:zero_kara_hajimeru_isekai_seikatsu")
+                )),
+                Value::String("tags : _3ad".to_string()),
+                Value::String(format!("tags : {}", schema::fts_token("foo:bar"))),
+            ]
+        );
+        assert!(query.conditions[2].starts_with("NOT ("));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("score:many").is_err());
+        assert!(parse("order:name").is_err());
+        assert!(parse("filesize:>big").is_err());
+    }
+
+    #[test]
+    fn execute() {
+        assert_eq!(search("long_hair"), ["yandere 2", "yandere 1"]);
+        assert_eq!(search("hair"), ["konachan 1"]);
+        assert_eq!(
+            search("long* order:score"),
+            ["yandere 2", "yandere 1", "konachan 1"]
+        );
+        assert_eq!(search("*_hair -blue_eyes"), ["yandere 2"]);
+        assert_eq!(search("rating:s order:score"), ["yandere 3", "yandere 1"]);
+        assert_eq!(search("platform:konachan"), ["konachan 2", "konachan 1"]);
+        assert_eq!(
+            search("re:zero_kara_hajimeru_isekai_seikatsu"),
+            ["konachan 2"]
+        );
+        assert_eq!(search(":d"), ["konachan 2"]);
+        assert_eq!(search("score:10..60 order:id_asc limit:1"), ["yandere 1"]);
+    }
+}
@@ -16,60 +16,56 @@ use tokio::{
 };
 
 // local
-use crate::consts::BLOCKSIZE;
+use crate::{
+    consts::{BLOCKSIZE, PART_EXT, SEGMENTS_EXT},
+    statics::PROGRESS,
+};
 
 // used by mvf
-async fn hard_move<F, Fu>(src: &str, dest: &str, on_success: F)
+async fn hard_move<F, Fu>(src: &str, dest: &str, on_success: F) -> Result<(), crate::Error>
 where
     F: FnOnce() -> Fu,
     Fu: Future<Output = ()>,
 {
-    let src_size = match fs::metadata(src).await {
-        Ok(m) => m.st_size(),
-        Err(e) => panic!("{e:?}"),
-    };
-    let src_open: File = fs::OpenOptions::new().read(true).open(&src).await.unwrap();
+    let src_size = fs::metadata(src)
+        .await
+        .map_err(|e| crate::Error::file(src, e))?
+        .st_size();
+    let src_open: File = fs::OpenOptions::new()
+        .read(true)
+        .open(&src)
+        .await
+        .map_err(|e| crate::Error::file(src, e))?;
     let dest_open: File = fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(dest)
         .await
-        .unwrap();
-    let mut buf: Vec<u8> = Vec::with_capacity(BLOCKSIZE);
-    let mut reader: BufReader<File> = BufReader::new(src_open);
-    let mut writer: BufWriter<File> = BufWriter::new(dest_open);
-    loop {
-        match reader.read(&mut buf).await {
-            Ok(v) => {
-                if v == 0
-                    && match fs::metadata(src).await {
-                        Ok(m) => m.st_size(),
-                        Err(e) => panic!("{:?}", e),
-                    } == src_size
-                {
-                    on_success().await;
-                    return;
-                }
-                match writer.write_all(&buf).await {
-                    Ok(_) => {
-                        buf.clear();
-                    }
-                    Err(e) => {
-                        println!("unable to write to file ({}) to move!", src);
-                        panic!("{:?}", e);
-                    }
-                }
-                buf.clear();
-            }
-            Err(e) => {
-                println!("unable to read file ({}) to move!", src);
-                panic!("{:?}", e)
-            }
-        }
+        .map_err(|e| crate::Error::file(dest, e))?;
+    let mut reader: BufReader<File> = BufReader::with_capacity(BLOCKSIZE, src_open);
+    let mut writer: BufWriter<File> = BufWriter::with_capacity(BLOCKSIZE, dest_open);
+    let written = tokio::io::copy(&mut reader, &mut writer)
+        .await
+        .map_err(|e| crate::Error::file(dest, e))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| crate::Error::file(dest, e))?;
+    if written != src_size {
+        return Err(crate::Error::file(
+            dest,
+            Error::other(format!("copied {written} bytes out of {src_size}")),
+        ));
     }
+    fs::remove_file(src)
+        .await
+        .map_err(|e| crate::Error::file(src, e))?;
+    on_success().await;
+    return Ok(());
 }
 
-pub async fn mvf<F, Fu, P>(_src: P, _dest: P, on_success: F)
+pub async fn mvf<F, Fu, P>(_src: P, _dest: P, on_success: F) -> Result<(), crate::Error>
 where
     F: FnOnce() -> Fu,
     Fu: Future<Output = ()>,
@@ -77,38 +73,22 @@ where
 {
     let src = _src.as_ref();
     let dest = _dest.as_ref();
-    enum MoveError {
-        ENOENT = 1,
-        EACCESS = 12,
-        EXDEV = 18,
-        UNKNOWN = -1,
-    }
-
-    impl From<i32> for MoveError {
-        fn from(value: i32) -> Self {
-            match value {
-                2 => Self::ENOENT,
-                12 => Self::EACCESS,
-                18 => Self::EXDEV,
-                _ => Self::UNKNOWN,
-            }
-        }
-    }
+    // EXDEV
+    const CROSS_DEVICE: i32 = 18;
 
+    if let Some(parent) = Path::new(dest).parent() {
+        recursive_dir_create(parent)
+            .await
+            .map_err(|e| crate::Error::file(parent, e))?;
+    }
     match fs::rename(&src, &dest).await {
-        Ok(_) => (),
+        Ok(_) => {
+            on_success().await;
+            return Ok(());
+        }
         Err(e) => match e.raw_os_error() {
-            Some(code) => match MoveError::from(code) {
-                MoveError::ENOENT => {
-                    eprintln!("unable to move, no such file or directory: {src}");
-                }
-                MoveError::EACCESS => {
-                    eprintln!("cannot move, permission denied: {src}");
-                }
-                MoveError::EXDEV => return hard_move(src, dest, on_success).await,
-                _ => panic!("{e:?}"),
-            },
-            None => panic!("{e:?}"),
+            Some(CROSS_DEVICE) => return hard_move(src, dest, on_success).await,
+            _ => return Err(crate::Error::file(src, e)),
         },
     }
 }
@@ -122,13 +102,10 @@ pub async fn recursive_dir_create<P: AsRef<Path>>(path: P) -> Result<(), Error>
             Err(e) => match e.kind() {
                 ErrorKind::NotFound => match fs::create_dir(&path_build).await {
                     Ok(_) => (),
-                    Err(e) => match e.kind() {
-                        ErrorKind::PermissionDenied => return Err(e),
-                        _ => panic!("{:?}", e),
-                    },
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                    Err(e) => return Err(e),
                 },
-                ErrorKind::PermissionDenied => return Err(e),
-                _ => panic!("{:?}", e),
+                _ => return Err(e),
             },
         }
     }
@@ -137,10 +114,9 @@ pub async fn recursive_dir_create<P: AsRef<Path>>(path: P) -> Result<(), Error>
 
 pub async fn recursive_file_create<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     let p = path.as_ref();
-    match recursive_dir_create(p.parent().unwrap()).await {
-        Ok(_) => (),
-        Err(e) => return Err(e),
-    };
+    if let Some(parent) = p.parent() {
+        recursive_dir_create(parent).await?;
+    }
     match path.as_ref().metadata() {
         Ok(_) => return Ok(()),
         Err(e) => match e.kind() {
@@ -148,7 +124,7 @@ pub async fn recursive_file_create<P: AsRef<Path>>(path: P) -> Result<(), Error>
                 Ok(_) => return Ok(()),
                 Err(e) => return Err(e),
             },
-            _ => panic!("{:?}", e),
+            _ => return Err(e),
         },
     }
 }
@@ -163,13 +139,10 @@ pub fn recursive_dir_create_blocking<P: AsRef<Path>>(path: P) -> Result<(), Erro
             Err(e) => match e.kind() {
                 ErrorKind::NotFound => match fs::create_dir(&path_build) {
                     Ok(_) => (),
-                    Err(e) => match e.kind() {
-                        ErrorKind::PermissionDenied => return Err(e),
-                        _ => panic!("{:?}", e),
-                    },
+                    Err(e) if e.kind() == ErrorKind::AlreadyExists => (),
+                    Err(e) => return Err(e),
                 },
-                ErrorKind::PermissionDenied => return Err(e),
-                _ => panic!("{:?}", e),
+                _ => return Err(e),
             },
         }
     }
@@ -179,10 +152,9 @@ pub fn recursive_dir_create_blocking<P: AsRef<Path>>(path: P) -> Result<(), Erro
 pub fn recursive_file_create_blocking<P: AsRef<Path>>(path: P) -> Result<(), Error> {
     use std::fs;
     let p = path.as_ref();
-    match recursive_dir_create_blocking(p.parent().unwrap()) {
-        Ok(_) => (),
-        Err(e) => return Err(e),
-    };
+    if let Some(parent) = p.parent() {
+        recursive_dir_create_blocking(parent)?;
+    }
     match path.as_ref().metadata() {
         Ok(_) => return Ok(()),
         Err(e) => match e.kind() {
@@ -190,7 +162,270 @@ pub fn recursive_file_create_blocking<P: AsRef<Path>>(path: P) -> Result<(), Err
                 Ok(_) => return Ok(()),
                 Err(e) => return Err(e),
             },
-            _ => panic!("{:?}", e),
+            _ => return Err(e),
         },
     }
 }
+
+/// hex encoded md5 checksum of a file
+pub async fn md5sum<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let file: File = fs::OpenOptions::new().read(true).open(path).await?;
+    let mut reader: BufReader<File> = BufReader::with_capacity(BLOCKSIZE, file);
+    let mut buf: Vec<u8> = vec![0; BLOCKSIZE];
+    let mut context = md5::Context::new();
+    loop {
+        match reader.read(&mut buf).await? {
+            0 => break,
+            n => context.consume(&buf[..n]),
+        }
+    }
+    return Ok(format!("{:x}", context.compute()));
+}
+
+/// regular files and symlinks under `dirs`, unreadable directories are reported and skipped
+pub async fn list_files(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut ret: Vec<PathBuf> = Vec::new();
+    let mut stack: Vec<PathBuf> = dirs.to_vec();
+    while let Some(dir) = stack.pop() {
+        let mut entries = match fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                PROGRESS.eprintln(format!("{}: {e}", dir.display()));
+                continue;
+            }
+        };
+        loop {
+            match entries.next_entry().await {
+                Ok(Some(entry)) => match entry.file_type().await {
+                    Ok(t) if t.is_dir() => stack.push(entry.path()),
+                    Ok(t) if t.is_file() || t.is_symlink() => ret.push(entry.path()),
+                    Ok(_) => (),
+                    Err(e) => PROGRESS.eprintln(format!("{}: {e}", entry.path().display())),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    PROGRESS.eprintln(format!("{}: {e}", dir.display()));
+                    break;
+                }
+            }
+        }
+    }
+    ret.sort();
+    return ret;
+}
+
+/// downloads that didn't finish
+pub fn is_partial(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e == PART_EXT || e == SEGMENTS_EXT)
+}
+
+/// the part of a path template before the first `{key}`, e.g.
+/// `/home/user/booruchan/{platform}/{id}` -> `/home/user/booruchan`
+pub fn template_root(template: &str) -> PathBuf {
+    Path::new(template)
+        .components()
+        .take_while(|c| !c.as_os_str().to_string_lossy().contains('{'))
+        .collect::<PathBuf>()
+}
+
+/// whether `path` could have been written by `template`. `{id}` only matches digits,
+/// `{md5}` 32 hex digits, `{file_ext}` anything without a dot and every other `{key}`
+/// anything within a path component.
+pub fn matches_template(template: &Path, path: &Path) -> bool {
+    let (mut template, mut path) = (template.components(), path.components());
+    loop {
+        match (template.next(), path.next()) {
+            (Some(t), Some(p)) => {
+                if !matches_component(
+                    t.as_os_str().to_string_lossy().as_ref(),
+                    p.as_os_str().to_string_lossy().as_ref(),
+                ) {
+                    return false;
+                }
+            }
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn matches_component(pattern: &str, name: &str) -> bool {
+    let (literal, mut rest) = match pattern.find('{') {
+        None => return pattern == name,
+        Some(start) => pattern.split_at(start),
+    };
+    if !name.starts_with(literal) {
+        return false;
+    }
+    // the positions in `name` where the rest of the pattern can start, tracking all of them
+    // at once instead of backtracking keeps adjacent keys like `{artist}{tags}` from being
+    // exponential
+    let mut reachable: Vec<bool> = vec![false; name.len() + 1];
+    reachable[literal.len()] = true;
+    while let Some(body) = rest.strip_prefix('{') {
+        let (key, after) = match body.find('}') {
+            Some(end) => (&body[..end], &body[end + 1..]),
+            None => (body, ""),
+        };
+        let (literal, next) = after.split_at(after.find('{').unwrap_or(after.len()));
+        // `tags[0:3]` is a range of `tags`
+        let key = key.split('[').next().unwrap_or_default();
+        let mut next_reachable: Vec<bool> = vec![false; name.len() + 1];
+        for start in (0..=name.len()).filter(|i| reachable[*i]) {
+            for end in (start..=name.len()).filter(|i| name.is_char_boundary(*i)) {
+                if matches_key(key, &name[start..end]) && name[end..].starts_with(literal) {
+                    next_reachable[end + literal.len()] = true;
+                }
+            }
+        }
+        reachable = next_reachable;
+        rest = next;
+    }
+    return reachable[name.len()];
+}
+
+fn matches_key(key: &str, value: &str) -> bool {
+    match key {
+        "id" => !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()),
+        "md5" => value.len() == 32 && value.bytes().all(|b| b.is_ascii_hexdigit()),
+        "file_ext" => !value.is_empty() && !value.contains('.'),
+        _ => true,
+    }
+}
+
+/// the path without `.` components and duplicate separators
+pub fn normalize(path: &str) -> PathBuf {
+    Path::new(path).components().collect()
+}
+
+/// space available to unprivileged users on the filesystem containing `path`,
+/// the nearest existing ancestor is used when `path` doesn't exist yet.
+#[allow(clippy::unnecessary_cast)]
+pub fn available_space<P: AsRef<Path>>(path: P) -> Result<u64, Error> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let mut path: &Path = path.as_ref();
+    while !path.exists() {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => path = parent,
+            _ => {
+                path = Path::new(".");
+                break;
+            }
+        }
+    }
+    let c_path = CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    // SAFETY: statvfs only writes into `stat`, `c_path` is a valid nul terminated string
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(Error::last_os_error());
+    }
+    return Ok(stat.f_bavail as u64 * stat.f_frsize as u64);
+}
+
+/// parses sizes like `20 GB`, `512MiB` or `1048576`, units are powers of 1024.
+pub fn parse_size(string: &str) -> Option<u64> {
+    let string = string.trim();
+    let split = string
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(string.len());
+    let (number, unit) = string.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return None,
+    };
+    return Some((number * multiplier as f64) as u64);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(template: &str, path: &str) -> bool {
+        matches_template(&normalize(template), &normalize(path))
+    }
+
+    #[test]
+    fn template_matches_files_it_writes() {
+        let template = "/archive/yandere/{id}.{file_ext}";
+        assert!(matches(template, "/archive/yandere/123.jpg"));
+        assert!(matches(template, "/archive/./yandere//123.png"));
+        assert!(matches(
+            "/archive/{md5}.{file_ext}",
+            "/archive/0123456789abcdef0123456789ABCDEF.jpg"
+        ));
+        assert!(matches(
+            "/archive/{rating}/yande.re {id} {tags}.{file_ext}",
+            "/archive/s/yande.re 123 long_hair v1.5.jpg"
+        ));
+        assert!(matches(
+            "/archive/{artist[0:1]}/{id}.{file_ext}",
+            "/archive/someone/5.gif"
+        ));
+        assert!(matches("/archive/{id}", "/archive/42"));
+    }
+
+    #[test]
+    fn template_rejects_other_files() {
+        let template = "/archive/yandere/{id}.{file_ext}";
+        assert!(!matches(template, "/archive/yandere/notes.txt"));
+        assert!(!matches(template, "/archive/yandere/123.jpg.bak"));
+        assert!(!matches(template, "/archive/yandere/123"));
+        assert!(!matches(template, "/archive/yandere/.jpg"));
+        assert!(!matches(template, "/archive/yandere/sub/123.jpg"));
+        assert!(!matches(template, "/archive/123.jpg"));
+        assert!(!matches(template, "/archive/konachan/123.jpg"));
+        assert!(!matches("/archive/{md5}.{file_ext}", "/archive/thumbs.db"));
+        assert!(!matches(
+            "/archive/yande.re {id} {tags}.{file_ext}",
+            "/archive/yande.re x1 tag.jpg"
+        ));
+        assert!(!matches("/archive/{id}", "/archive/42.part"));
+    }
+
+    #[test]
+    fn template_with_adjacent_keys() {
+        let template = format!("/archive/{}.{{file_ext}}", "{tags}".repeat(12));
+        assert!(matches(&template, "/archive/a.jpg"));
+        assert!(matches(
+            &template,
+            &format!("/archive/{}.jpg", "a".repeat(200))
+        ));
+        // nothing matches, every split of the name has to be ruled out
+        assert!(!matches(
+            &template,
+            &format!("/archive/{}", "a".repeat(200))
+        ));
+        assert!(matches(
+            "/archive/{id}{md5}",
+            "/archive/120123456789abcdef0123456789abcdef"
+        ));
+        assert!(!matches(
+            "/archive/{id}{md5}",
+            "/archive/0123456789abcdef0123456789abcdef"
+        ));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1048576"), Some(1 << 20));
+        assert_eq!(parse_size("0"), Some(0));
+        assert_eq!(parse_size("512MiB"), Some(512 << 20));
+        assert_eq!(parse_size(" 20 GB "), Some(20 << 30));
+        assert_eq!(parse_size("1.5k"), Some(1536));
+        assert_eq!(parse_size("2 tb"), Some(2 << 40));
+        assert_eq!(parse_size("100 B"), Some(100));
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("GB"), None);
+        assert_eq!(parse_size("-1 GB"), None);
+        assert_eq!(parse_size("1 PB"), None);
+        assert_eq!(parse_size("1.2.3 MB"), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! the `verify` command, checks that every saved post still has its file with the recorded
+//! size and md5. uploaded files are only checked for their existence and size.
+
+use std::{collections::HashSet, fmt, io::ErrorKind};
+
+// crate
+use serde::Serialize;
+use sqlite::{Connection, State};
+
+// local
+use crate::{
+    rclone, schema,
+    search::clouds,
+    utils,
+    worker::{read_entry, requeue_entry, DbEntry, COLUMNS},
+    Config, Error,
+};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "problem", rename_all = "snake_case")]
+pub enum Problem {
+    Missing,
+    Size {
+        expected: u64,
+        found: u64,
+    },
+    Md5 {
+        found: String,
+    },
+    /// the file couldn't be checked
+    Unreadable {
+        error: String,
+    },
+    CompressedMissing,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Missing => write!(f, "missing"),
+            Problem::Size { expected, found } => {
+                write!(f, "size is {found} bytes instead of {expected}")
+            }
+            Problem::Md5 { found } => write!(f, "md5 is {found}"),
+            Problem::Unreadable { error } => write!(f, "unable to check: {error}"),
+            Problem::CompressedMissing => write!(f, "compressed file is missing"),
+        }
+    }
+}
+
+impl Problem {
+    /// the original is gone or corrupt, downloading it again fixes it
+    fn is_broken(&self) -> bool {
+        matches!(
+            self,
+            Problem::Missing | Problem::Size { .. } | Problem::Md5 { .. }
+        )
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub platform: String,
+    pub id: i64,
+    pub location: String,
+    #[serde(flatten)]
+    pub problem: Problem,
+}
+
+/// existence and size of a local file or a file on `cloud`
+async fn stat(path: &str, cloud: Option<&str>) -> Result<Option<u64>, Problem> {
+    let unreadable = |e: String| Problem::Unreadable { error: e };
+    match cloud {
+        Some(cloud) => {
+            return rclone::size(format!("{cloud}:{path}").as_str())
+                .await
+                .map_err(|e| unreadable(e.to_string()))
+        }
+        None => match tokio::fs::metadata(path).await {
+            Ok(m) => return Ok(Some(m.len())),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(unreadable(e.to_string())),
+        },
+    }
+}
+
+/// `near_duplicate` posts point to the file of another post
+async fn check(entry: &DbEntry, cloud: Option<&str>, near_duplicate: bool) -> Vec<Problem> {
+    let mut ret: Vec<Problem> = Vec::new();
+    match stat(entry.path.as_str(), cloud).await {
+        Ok(None) => ret.push(Problem::Missing),
+        Ok(Some(_)) if near_duplicate => (),
+        Ok(Some(size)) => {
+            let expected = entry.file_size.and_then(|s| u64::try_from(s).ok());
+            match expected {
+                Some(expected) if expected != size => ret.push(Problem::Size {
+                    expected,
+                    found: size,
+                }),
+                // hashing doesn't work remotely without downloading the file
+                _ if cloud.is_some() => (),
+                _ => match utils::md5sum(entry.path.as_str()).await {
+                    Ok(md5) if md5 != entry.md5 => ret.push(Problem::Md5 { found: md5 }),
+                    Ok(_) => (),
+                    Err(e) => ret.push(Problem::Unreadable {
+                        error: e.to_string(),
+                    }),
+                },
+            }
+        }
+        Err(problem) => ret.push(problem),
+    }
+    if let Some(ref compress_path) = entry.compress_path {
+        match stat(compress_path.as_str(), cloud).await {
+            Ok(None) => ret.push(Problem::CompressedMissing),
+            Ok(Some(_)) => (),
+            Err(problem) => ret.push(problem),
+        }
+    }
+    return ret;
+}
+
+fn near_duplicates(connection: &Connection) -> Result<HashSet<(String, i64)>, Error> {
+    let mut statement =
+        connection.prepare("SELECT platform, id FROM crossref WHERE action = 'near_duplicate'")?;
+    let mut ret: HashSet<(String, i64)> = HashSet::new();
+    while let State::Row = statement.next()? {
+        ret.insert((
+            statement.read::<String, _>(0)?,
+            statement.read::<i64, _>(1)?,
+        ));
+    }
+    return Ok(ret);
+}
+
+/// the `verify` command, prints the problems found. with `requeue` the posts with a missing
+/// or corrupt file are recorded as failed, so `retry-failed` downloads them again.
+pub async fn run(config: &Config, requeue: bool, json: bool) -> Result<(), Error> {
+    let clouds = clouds(config);
+    let mut reports: Vec<Report> = Vec::new();
+    let mut checked: usize = 0;
+    let mut requeued: usize = 0;
+    for (database, tables) in config.databases() {
+        let connection = Connection::open(&database)?;
+        schema::migrate(&connection)?;
+        let near_duplicates = near_duplicates(&connection)?;
+        for platform in tables {
+            let cloud = clouds.get(platform).copied().flatten();
+            // read first, the files are checked without holding the statement
+            let mut statement = connection.prepare(format!("SELECT {COLUMNS} FROM {platform}"))?;
+            let mut entries: Vec<DbEntry> = Vec::new();
+            while let State::Row = statement.next()? {
+                entries.push(read_entry(&statement)?);
+            }
+            drop(statement);
+            for entry in entries {
+                checked += 1;
+                let near_duplicate = near_duplicates.contains(&(platform.to_string(), entry.id));
+                let problems = check(&entry, cloud, near_duplicate).await;
+                if requeue {
+                    if let Some(problem) = problems.iter().find(|p| p.is_broken()) {
+                        requeue_entry(&connection, platform, &entry, format!("verify: {problem}"))?;
+                        requeued += 1;
+                    }
+                }
+                for problem in problems {
+                    let path = match problem {
+                        Problem::CompressedMissing => entry.compress_path.as_deref(),
+                        _ => None,
+                    }
+                    .unwrap_or(entry.path.as_str());
+                    let location = match cloud {
+                        Some(cloud) => format!("{cloud}:{path}"),
+                        None => path.to_string(),
+                    };
+                    if !json {
+                        println!("{platform} {id} {problem}: {location}", id = entry.id);
+                    }
+                    reports.push(Report {
+                        platform: platform.to_string(),
+                        id: entry.id,
+                        location,
+                        problem,
+                    });
+                }
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+    eprintln!("checked {checked} posts, found {} problems", reports.len());
+    if requeued > 0 {
+        eprintln!(
+            "{requeued} posts were recorded as failed, run retry-failed to download them again"
+        );
+    }
+    return Ok(());
+}
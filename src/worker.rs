@@ -1,17 +1,20 @@
-use crate::utils;
+use crate::{consts::BUSY_TIMEOUT, dupes, schema, statics::PROGRESS, utils, Config, Error};
+use serde::{Deserialize, Serialize};
 use sqlite::{Connection, ConnectionThreadSafe, State, Statement, Value};
+use std::collections::HashMap;
+use std::io::ErrorKind;
 #[cfg(target_os = "android")]
 use std::os::android::fs::MetadataExt;
 #[cfg(target_os = "linux")]
 use std::os::linux::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::{io::ErrorKind, process::exit};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::{
     sync::{mpsc, oneshot},
     task,
 };
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub struct DbEntry {
     pub id: i64,
     pub md5: String,
@@ -19,17 +22,148 @@ pub struct DbEntry {
     pub tags: Option<String>,
     pub path: String,
     pub compress_path: Option<String>,
+    pub rating: Option<String>,
+    pub score: Option<i64>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub file_size: Option<i64>,
+    pub file_ext: Option<String>,
+    /// unix timestamps
+    pub created_at: Option<i64>,
+    pub updated_at: Option<i64>,
+    pub author: Option<String>,
+    pub parent_id: Option<i64>,
+    pub status: Option<String>,
+    /// url of the downloaded file
+    pub file_url: Option<String>,
+    /// perceptual hash of the image, see `dupes::dhash`
+    pub dhash: Option<i64>,
+}
+
+impl DbEntry {
+    /// width times height, 0 when unknown
+    pub fn resolution(&self) -> i64 {
+        self.width.unwrap_or(0) * self.height.unwrap_or(0)
+    }
 }
 
 pub struct Insert {
     pub platform: &'static str,
     pub entry: DbEntry,
+    /// name and type of every tag of the post, the type of an existing tag is kept
+    /// when it's None
+    pub tags: Vec<(String, Option<&'static str>)>,
+    /// the same file saved by another platform
+    pub crossref: Option<CrossRef>,
+    /// imported without --move, the file isn't in the layout of the platform
+    pub imported: bool,
+}
+
+/// a post of another platform with the same md5
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossEntry {
+    pub platform: String,
+    pub entry: DbEntry,
+}
+
+pub struct CrossRef {
+    pub platform: String,
+    pub id: i64,
+    /// `skip`, `hardlink`, `symlink`, `download` or `near_duplicate`
+    pub action: &'static str,
+}
+
+/// looks up all `ids` with a single query, only the saved ones are in the map
+pub struct SelectMany {
+    pub platform: &'static str,
+    pub ids: Vec<i64>,
+    pub sender: oneshot::Sender<HashMap<i64, DbEntry>>,
+}
+
+/// looks up `md5s` in the tables of the other platforms, one entry per md5
+pub struct SelectMd5 {
+    pub platform: &'static str,
+    pub md5s: Vec<String>,
+    pub sender: oneshot::Sender<HashMap<String, CrossEntry>>,
+}
+
+/// looks up the saved image with the highest resolution within `distance` of `dhash`,
+/// the post itself is left out
+pub struct SelectNear {
+    pub platform: &'static str,
+    pub id: i64,
+    pub dhash: i64,
+    pub distance: u32,
+    pub sender: oneshot::Sender<Option<CrossEntry>>,
+}
+
+/// whether the file of a post has to stay where it is when the post is saved again.
+/// either it's shared with other posts through `skip`, `symlink` or `near_duplicate`,
+/// or it was imported without --move.
+pub struct SelectPinned {
+    pub platform: &'static str,
+    pub id: i64,
+    pub sender: oneshot::Sender<bool>,
+}
+
+/// a post that couldn't be saved, recorded in the `failed` table
+pub struct Fail {
+    pub platform: &'static str,
+    pub id: i64,
+    pub url: String,
+    pub reason: String,
+}
+
+/// removes a post from the `failed` table
+pub struct Resolve {
+    pub platform: &'static str,
+    pub id: i64,
+}
+
+pub struct SelectFailed {
+    pub platform: &'static str,
+    pub sender: oneshot::Sender<Vec<FailedEntry>>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct FailedEntry {
+    pub id: i64,
+    pub url: String,
+    pub reason: String,
+    pub attempts: i64,
+    /// unix timestamp
+    pub last_attempt: i64,
 }
 
-pub struct Select {
+/// adds a post to the download queue, `post` is serialized by the platform
+pub struct Enqueue {
     pub platform: &'static str,
     pub id: i64,
-    pub sender: oneshot::Sender<Option<DbEntry>>,
+    pub post: String,
+}
+
+/// claims the oldest queued post of a platform
+pub struct Dequeue {
+    pub platform: &'static str,
+    pub sender: oneshot::Sender<Option<QueueEntry>>,
+}
+
+/// removes a post from the download queue once it's handled
+pub struct Finish {
+    pub platform: &'static str,
+    pub id: i64,
+}
+
+/// number of queued posts of a platform that aren't claimed yet
+pub struct QueueLength {
+    pub platform: &'static str,
+    pub sender: oneshot::Sender<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub id: i64,
+    pub post: String,
 }
 
 pub struct ImageRequest {
@@ -37,39 +171,175 @@ pub struct ImageRequest {
     pub dest: Vec<String>,
     pub size: (u32, u32),
     pub fallback: Option<String>,
-    pub response_channel: oneshot::Sender<Option<PathBuf>>,
+    pub response_channel: oneshot::Sender<Result<PathBuf, Error>>,
 }
 
 pub enum Operation {
-    Insert(Insert),
-    Select(Select),
+    Insert(Box<Insert>),
+    SelectMany(SelectMany),
+    SelectMd5(SelectMd5),
+    SelectNear(SelectNear),
+    SelectPinned(SelectPinned),
     Image(ImageRequest),
+    Fail(Fail),
+    Resolve(Resolve),
+    SelectFailed(SelectFailed),
+    Enqueue(Enqueue),
+    Dequeue(Dequeue),
+    Finish(Finish),
+    QueueLength(QueueLength),
     Close,
 }
 
 pub struct Worker {
     pub buf: mpsc::Receiver<Operation>,
     connection: ConnectionThreadSafe,
+    database: PathBuf,
+    /// platform tables looked up for duplicates, including the attached databases
+    tables: schema::Tables,
 }
 
 impl Worker {
-    pub fn new<D: AsRef<Path>>(database: D, receiver: mpsc::Receiver<Operation>) -> Self {
-        Self {
+    pub fn new<D: AsRef<Path>>(
+        database: D,
+        receiver: mpsc::Receiver<Operation>,
+    ) -> Result<Self, Error> {
+        let mut connection = Connection::open_thread_safe(database.as_ref())?;
+        // other workers write to the databases attached by this one
+        connection.set_busy_timeout(BUSY_TIMEOUT)?;
+        schema::migrate(&connection)?;
+        // claimed posts were in progress when the last run stopped
+        connection.execute("UPDATE queue SET claimed = 0")?;
+        Ok(Self {
             buf: receiver,
-            connection: Connection::open_thread_safe(database.as_ref()).unwrap(),
-        }
+            connection,
+            database: database.as_ref().to_path_buf(),
+            tables: schema::platform_tables()
+                .into_iter()
+                .map(|t| ("main".to_string(), t))
+                .collect(),
+        })
+    }
+
+    /// attaches the databases of the other platforms so files saved by them are found,
+    /// has to be called once every worker has created its database.
+    pub fn attach(&mut self, config: &Config) -> Result<(), Error> {
+        self.tables = schema::attach(&self.connection, &self.database, config.databases())?;
+        return Ok(());
     }
 
     pub async fn main(&mut self) {
         loop {
             match self.buf.recv().await {
                 Some(opt) => match opt {
-                    Operation::Insert(i) => self.insert(i).await,
-                    Operation::Select(s) => self.select(s).await,
-                    Operation::Image(r) => {
-                        let t = task::spawn_blocking(|| image_resize(r));
-                        t.await.unwrap();
+                    Operation::Insert(i) => {
+                        let (platform, id) = (i.platform, i.entry.id);
+                        if let Err(e) = self.insert(*i).await {
+                            PROGRESS
+                                .eprintln(format!("{platform}: post {id}: unable to save: {e}"));
+                        }
                     }
+                    Operation::SelectMany(s) => match self.select_many(s.platform, &s.ids) {
+                        Ok(entries) => {
+                            s.sender.send(entries).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: {e}", s.platform));
+                            s.sender.send(HashMap::new()).ok();
+                        }
+                    },
+                    Operation::SelectMd5(s) => match self.select_md5(s.platform, &s.md5s) {
+                        Ok(entries) => {
+                            s.sender.send(entries).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: {e}", s.platform));
+                            s.sender.send(HashMap::new()).ok();
+                        }
+                    },
+                    Operation::SelectNear(s) => match self.select_near(&s) {
+                        Ok(entry) => {
+                            s.sender.send(entry).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: post {}: {e}", s.platform, s.id));
+                            s.sender.send(None).ok();
+                        }
+                    },
+                    Operation::SelectPinned(s) => match self.select_pinned(s.platform, s.id) {
+                        Ok(pinned) => {
+                            s.sender.send(pinned).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: post {}: {e}", s.platform, s.id));
+                            // leaving the file where it is can't break another post
+                            s.sender.send(true).ok();
+                        }
+                    },
+                    Operation::Image(mut r) => {
+                        let t = task::spawn_blocking(move || {
+                            let resp = image_resize(&mut r);
+                            r.response_channel.send(resp).ok();
+                        });
+                        if let Err(e) = t.await {
+                            PROGRESS.eprintln(format!("image worker panicked: {e}"));
+                        }
+                    }
+                    Operation::Fail(f) => {
+                        let (platform, id) = (f.platform, f.id);
+                        if let Err(e) = self.fail(f) {
+                            PROGRESS.eprintln(format!(
+                                "{platform}: post {id}: unable to record failure: {e}"
+                            ));
+                        }
+                    }
+                    Operation::Resolve(r) => {
+                        if let Err(e) = self.resolve(r.platform, r.id) {
+                            PROGRESS.eprintln(format!("{}: post {}: {e}", r.platform, r.id));
+                        }
+                    }
+                    Operation::SelectFailed(s) => match self.select_failed(s.platform) {
+                        Ok(entries) => {
+                            s.sender.send(entries).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!(
+                                "{}: unable to read failed posts: {e}",
+                                s.platform
+                            ));
+                            s.sender.send(Vec::new()).ok();
+                        }
+                    },
+                    Operation::Enqueue(q) => {
+                        let (platform, id) = (q.platform, q.id);
+                        if let Err(e) = self.enqueue(q) {
+                            PROGRESS
+                                .eprintln(format!("{platform}: post {id}: unable to queue: {e}"));
+                        }
+                    }
+                    Operation::Dequeue(d) => match self.dequeue(d.platform) {
+                        Ok(entry) => {
+                            d.sender.send(entry).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: unable to read queue: {e}", d.platform));
+                            d.sender.send(None).ok();
+                        }
+                    },
+                    Operation::Finish(f) => {
+                        if let Err(e) = self.finish(f.platform, f.id) {
+                            PROGRESS.eprintln(format!("{}: post {}: {e}", f.platform, f.id));
+                        }
+                    }
+                    Operation::QueueLength(q) => match self.queue_length(q.platform) {
+                        Ok(length) => {
+                            q.sender.send(length).ok();
+                        }
+                        Err(e) => {
+                            PROGRESS.eprintln(format!("{}: unable to read queue: {e}", q.platform));
+                            q.sender.send(0).ok();
+                        }
+                    },
                     Operation::Close => self.buf.close(),
                 },
                 None => return,
@@ -77,166 +347,549 @@ impl Worker {
         }
     }
 
-    async fn select(&self, entry: Select) {
-        let mut statement = match self.connection.prepare(format!(
-            "SELECT * FROM {table} WHERE id = ?",
-            table = entry.platform
-        )) {
-            Ok(mut s) => {
-                s.bind((1, entry.id)).unwrap();
-                s
+    fn select_many(&self, platform: &str, ids: &[i64]) -> Result<HashMap<i64, DbEntry>, Error> {
+        let mut ret: HashMap<i64, DbEntry> = HashMap::with_capacity(ids.len());
+        if ids.is_empty() {
+            return Ok(ret);
+        }
+        let mut statement = self.connection.prepare(format!(
+            "SELECT {COLUMNS} FROM {table} WHERE id IN ({params})",
+            table = platform,
+            params = vec!["?"; ids.len()].join(", ")
+        ))?;
+        for (i, id) in ids.iter().enumerate() {
+            statement.bind((i + 1, *id))?;
+        }
+        while let State::Row = statement.next()? {
+            let entry = read_entry(&statement)?;
+            ret.insert(entry.id, entry);
+        }
+        return Ok(ret);
+    }
+
+    fn select_md5(
+        &self,
+        platform: &str,
+        md5s: &[String],
+    ) -> Result<HashMap<String, CrossEntry>, Error> {
+        let mut ret: HashMap<String, CrossEntry> = HashMap::with_capacity(md5s.len());
+        if md5s.is_empty() {
+            return Ok(ret);
+        }
+        let params = vec!["?"; md5s.len()].join(", ");
+        let tables: Vec<&(String, &'static str)> =
+            self.tables.iter().filter(|(_, t)| *t != platform).collect();
+        if tables.is_empty() {
+            return Ok(ret);
+        }
+        let union = tables
+            .iter()
+            .map(|(schema, table)| {
+                format!(
+                    "SELECT {COLUMNS}, '{table}' AS platform FROM {schema}.{table} \
+                        WHERE md5 IN ({params})"
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" UNION ALL ");
+        let mut statement = self.connection.prepare(union)?;
+        for (i, md5) in md5s
+            .iter()
+            .cycle()
+            .take(md5s.len() * tables.len())
+            .enumerate()
+        {
+            statement.bind((i + 1, md5.as_str()))?;
+        }
+        while let State::Row = statement.next()? {
+            let entry = read_entry(&statement)?;
+            let platform = statement.read::<String, _>("platform")?;
+            ret.entry(entry.md5.clone())
+                .or_insert(CrossEntry { platform, entry });
+        }
+        return Ok(ret);
+    }
+
+    /// runs for every downloaded image, so only the columns needed to pick the closest match
+    /// are read. the full entry is read for the match alone.
+    fn select_near(&self, near: &SelectNear) -> Result<Option<CrossEntry>, Error> {
+        let union = self
+            .tables
+            .iter()
+            .enumerate()
+            .map(|(i, (schema, table))| {
+                format!(
+                    "SELECT id, dhash, width, height, {i} AS source FROM {schema}.{table} \
+                        WHERE dhash IS NOT NULL"
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" UNION ALL ");
+        let mut statement = self.connection.prepare(union)?;
+        // index of the table, id and resolution of the best match
+        let mut best: Option<(usize, i64, i64)> = None;
+        while let State::Row = statement.next()? {
+            let id = statement.read::<i64, _>("id")?;
+            let dhash = statement.read::<i64, _>("dhash")?;
+            let source = statement.read::<i64, _>("source")? as usize;
+            if dupes::distance(dhash, near.dhash) > near.distance
+                || (self.tables[source].1 == near.platform && id == near.id)
+            {
+                continue;
             }
-            Err(_) => {
-                entry.sender.send(None).unwrap();
-                return;
+            let resolution = statement.read::<Option<i64>, _>("width")?.unwrap_or(0)
+                * statement.read::<Option<i64>, _>("height")?.unwrap_or(0);
+            match best {
+                Some((_, _, r)) if r >= resolution => (),
+                _ => best = Some((source, id, resolution)),
             }
+        }
+        let (source, id) = match best {
+            Some((source, id, _)) => (source, id),
+            None => return Ok(None),
         };
-        let mut ret = DbEntry::default();
-        while let Ok(State::Row) = statement.next() {
-            ret.id = statement.read::<i64, _>(0).unwrap();
-            ret.md5 = statement.read::<String, _>(1).unwrap();
-            ret.source = statement.read::<Option<String>, _>(2).unwrap();
-            ret.tags = statement.read::<Option<String>, _>(3).unwrap();
-            ret.path = statement.read::<String, _>(4).unwrap();
-            ret.compress_path = statement.read::<Option<String>, _>(5).unwrap();
-        }
-        entry.sender.send(Some(ret)).unwrap();
-        return;
-    }
-
-    async fn insert(&self, db_entry: Insert) {
-        self.connection
-            .execute(format!(
-                "CREATE TABLE IF NOT EXISTS {table}(
-                    id INT PRIMARY KEY,
-                    md5 TEXT NOT NULL,
-                    source TEXT,
-                    tags TEXT,
-                    path TEXT NOT NULL,
-                    compress_path TEXT)",
-                table = db_entry.platform
-            ))
-            .unwrap();
+        let (schema, table) = &self.tables[source];
+        let mut statement = self.connection.prepare(format!(
+            "SELECT {COLUMNS} FROM {schema}.{table} WHERE id = ?"
+        ))?;
+        statement.bind((1, id))?;
+        let mut ret: Option<CrossEntry> = None;
+        while let State::Row = statement.next()? {
+            ret = Some(CrossEntry {
+                platform: table.to_string(),
+                entry: read_entry(&statement)?,
+            });
+        }
+        return Ok(ret);
+    }
 
+    fn select_pinned(&self, platform: &str, id: i64) -> Result<bool, Error> {
+        let mut schemas: Vec<&str> = self.tables.iter().map(|(s, _)| s.as_str()).collect();
+        schemas.dedup();
+        let union = schemas
+            .iter()
+            .map(|schema| {
+                format!(
+                    "SELECT 1 FROM {schema}.crossref
+                        WHERE (other_platform = ?1 AND other_id = ?2
+                            AND action IN ('skip', 'symlink', 'near_duplicate'))
+                        OR (platform = ?1 AND id = ?2 AND action IN ('skip', 'near_duplicate'))
+                    UNION ALL
+                    SELECT 1 FROM {schema}.imported WHERE platform = ?1 AND id = ?2"
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(" UNION ALL ");
+        let mut statement = self.connection.prepare(union)?;
+        statement.bind((1, platform))?;
+        statement.bind((2, id))?;
+        return Ok(matches!(statement.next()?, State::Row));
+    }
+
+    async fn insert(&self, db_entry: Insert) -> Result<(), Error> {
+        self.connection.execute("BEGIN")?;
+        match self.insert_entry(db_entry) {
+            Ok(_) => self.connection.execute("COMMIT")?,
+            Err(e) => {
+                self.connection.execute("ROLLBACK").ok();
+                return Err(e);
+            }
+        }
+        return Ok(());
+    }
+
+    fn insert_entry(&self, db_entry: Insert) -> Result<(), Error> {
+        self.insert_fts(
+            db_entry.platform,
+            db_entry.entry.id,
+            db_entry.entry.tags.as_deref().unwrap_or_default(),
+            db_entry.entry.source.as_deref().unwrap_or_default(),
+        )?;
+        let md5 = db_entry.entry.md5;
         let query: String = format!(
-            "INSERT OR REPLACE INTO {table} VALUES(?, ?, ?, ?, ?, ?)", /* id, md5, source, tags, path, compress_path*/
+            "INSERT OR REPLACE INTO {table}({COLUMNS})
+                VALUES(?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
             table = db_entry.platform,
         );
-        let mut statement: Statement = self.connection.prepare(query).unwrap();
-        statement
-            .bind_iter::<_, (usize, Value)>([
-                (1, Value::Integer(db_entry.entry.id)),
-                (2, Value::String(db_entry.entry.md5)),
-                (
-                    3,
-                    match db_entry.entry.source {
-                        Some(s) => Value::String(s),
-                        None => Value::Null,
-                    },
-                ),
-                (
-                    4,
-                    match db_entry.entry.tags {
-                        Some(s) => Value::String(s),
-                        None => Value::Null,
-                    },
-                ),
-                (5, Value::String(db_entry.entry.path)),
-                (
-                    6,
-                    match db_entry.entry.compress_path {
-                        Some(p) => Value::String(p),
-                        None => Value::Null,
-                    },
-                ),
-            ])
-            .unwrap();
-        loop {
-            match statement.next() {
-                Ok(s) => match s {
-                    State::Row => (),
-                    State::Done => break,
+        let mut statement: Statement = self.connection.prepare(query)?;
+        statement.bind_iter::<_, (usize, Value)>([
+            (1, Value::Integer(db_entry.entry.id)),
+            (2, Value::String(md5.clone())),
+            (
+                3,
+                match db_entry.entry.source {
+                    Some(s) => Value::String(s),
+                    None => Value::Null,
                 },
-                Err(e) => panic!("{:?}", e),
-            }
+            ),
+            (
+                4,
+                match db_entry.entry.tags {
+                    Some(s) => Value::String(s),
+                    None => Value::Null,
+                },
+            ),
+            (5, Value::String(db_entry.entry.path)),
+            (
+                6,
+                match db_entry.entry.compress_path {
+                    Some(p) => Value::String(p),
+                    None => Value::Null,
+                },
+            ),
+            (7, Value::from(db_entry.entry.rating)),
+            (8, Value::from(db_entry.entry.score)),
+            (9, Value::from(db_entry.entry.width)),
+            (10, Value::from(db_entry.entry.height)),
+            (11, Value::from(db_entry.entry.file_size)),
+            (12, Value::from(db_entry.entry.file_ext)),
+            (13, Value::from(db_entry.entry.created_at)),
+            (14, Value::from(db_entry.entry.updated_at)),
+            (15, Value::from(db_entry.entry.author)),
+            (16, Value::from(db_entry.entry.parent_id)),
+            (17, Value::from(db_entry.entry.status)),
+            (18, Value::from(db_entry.entry.file_url)),
+            (19, Value::from(db_entry.entry.dhash)),
+        ])?;
+        while let State::Row = statement.next()? {}
+        self.insert_tags(db_entry.platform, db_entry.entry.id, &db_entry.tags)?;
+        if let Some(crossref) = db_entry.crossref {
+            let mut statement = self
+                .connection
+                .prepare("INSERT OR REPLACE INTO crossref VALUES(?, ?, ?, ?, ?, ?)")?;
+            statement.bind_iter::<_, (usize, Value)>([
+                (1, Value::String(db_entry.platform.to_string())),
+                (2, Value::Integer(db_entry.entry.id)),
+                (3, Value::String(md5)),
+                (4, Value::String(crossref.platform)),
+                (5, Value::Integer(crossref.id)),
+                (6, Value::String(crossref.action.to_string())),
+            ])?;
+            while let State::Row = statement.next()? {}
+        }
+        if db_entry.imported {
+            let mut statement = self
+                .connection
+                .prepare("INSERT OR REPLACE INTO imported VALUES(?, ?)")?;
+            statement.bind((1, db_entry.platform))?;
+            statement.bind((2, db_entry.entry.id))?;
+            while let State::Row = statement.next()? {}
+        }
+        // saved posts don't need to be retried anymore
+        self.resolve(db_entry.platform, db_entry.entry.id)?;
+        return Ok(());
+    }
+
+    fn insert_fts(&self, platform: &str, id: i64, tags: &str, source: &str) -> Result<(), Error> {
+        let fts = schema::fts_table(platform);
+        let mut statement = self
+            .connection
+            .prepare(format!("DELETE FROM {fts} WHERE rowid = ?"))?;
+        statement.bind((1, id))?;
+        while let State::Row = statement.next()? {}
+        let mut statement = self.connection.prepare(format!(
+            "INSERT INTO {fts}(rowid, tags, source) VALUES(?, ?, ?)"
+        ))?;
+        statement.bind((1, id))?;
+        statement.bind((2, schema::fts_tags(tags).as_str()))?;
+        statement.bind((3, source))?;
+        while let State::Row = statement.next()? {}
+        return Ok(());
+    }
+
+    /// replaces the tags linked to a post
+    fn insert_tags(
+        &self,
+        platform: &str,
+        id: i64,
+        tags: &[(String, Option<&'static str>)],
+    ) -> Result<(), Error> {
+        let mut statement = self.connection.prepare(
+            "DELETE FROM post_tags
+                WHERE post = ? AND tag IN (SELECT id FROM tags WHERE platform = ?)",
+        )?;
+        statement.bind((1, id))?;
+        statement.bind((2, platform))?;
+        while let State::Row = statement.next()? {}
+        let mut insert_tag = self.connection.prepare(
+            "INSERT INTO tags(platform, name, type) VALUES(?, ?, ?)
+                ON CONFLICT(platform, name) DO UPDATE SET type = COALESCE(excluded.type, type)",
+        )?;
+        let mut insert_post_tag = self.connection.prepare(
+            "INSERT OR IGNORE INTO post_tags
+                SELECT ?, id FROM tags WHERE platform = ? AND name = ?",
+        )?;
+        for (name, _type) in tags {
+            insert_tag.reset()?;
+            insert_tag.bind((1, platform))?;
+            insert_tag.bind((2, name.as_str()))?;
+            insert_tag.bind((3, *_type))?;
+            while let State::Row = insert_tag.next()? {}
+            insert_post_tag.reset()?;
+            insert_post_tag.bind((1, id))?;
+            insert_post_tag.bind((2, platform))?;
+            insert_post_tag.bind((3, name.as_str()))?;
+            while let State::Row = insert_post_tag.next()? {}
+        }
+        return Ok(());
+    }
+
+    fn fail(&self, fail: Fail) -> Result<(), Error> {
+        return record_failure(&self.connection, fail);
+    }
+
+    fn resolve(&self, platform: &str, id: i64) -> Result<(), Error> {
+        let mut statement = self
+            .connection
+            .prepare("DELETE FROM failed WHERE platform = ? AND id = ?")?;
+        statement.bind((1, platform))?;
+        statement.bind((2, id))?;
+        while let State::Row = statement.next()? {}
+        return Ok(());
+    }
+
+    fn enqueue(&self, entry: Enqueue) -> Result<(), Error> {
+        let mut statement = self.connection.prepare(
+            "INSERT INTO queue(platform, id, post) VALUES(?, ?, ?)
+                ON CONFLICT(platform, id) DO UPDATE SET post = excluded.post",
+        )?;
+        statement.bind_iter::<_, (usize, Value)>([
+            (1, Value::String(entry.platform.to_string())),
+            (2, Value::Integer(entry.id)),
+            (3, Value::String(entry.post)),
+        ])?;
+        while let State::Row = statement.next()? {}
+        return Ok(());
+    }
+
+    fn dequeue(&self, platform: &str) -> Result<Option<QueueEntry>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, post FROM queue
+                WHERE platform = ? AND claimed = 0 ORDER BY rowid LIMIT 1",
+        )?;
+        statement.bind((1, platform))?;
+        let entry = match statement.next()? {
+            State::Row => QueueEntry {
+                id: statement.read::<i64, _>(0)?,
+                post: statement.read::<String, _>(1)?,
+            },
+            State::Done => return Ok(None),
+        };
+        let mut statement = self
+            .connection
+            .prepare("UPDATE queue SET claimed = 1 WHERE platform = ? AND id = ?")?;
+        statement.bind((1, platform))?;
+        statement.bind((2, entry.id))?;
+        while let State::Row = statement.next()? {}
+        return Ok(Some(entry));
+    }
+
+    fn finish(&self, platform: &str, id: i64) -> Result<(), Error> {
+        let mut statement = self
+            .connection
+            .prepare("DELETE FROM queue WHERE platform = ? AND id = ?")?;
+        statement.bind((1, platform))?;
+        statement.bind((2, id))?;
+        while let State::Row = statement.next()? {}
+        return Ok(());
+    }
+
+    fn queue_length(&self, platform: &str) -> Result<i64, Error> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT COUNT(*) FROM queue WHERE platform = ? AND claimed = 0")?;
+        statement.bind((1, platform))?;
+        let mut ret: i64 = 0;
+        while let State::Row = statement.next()? {
+            ret = statement.read::<i64, _>(0)?;
+        }
+        return Ok(ret);
+    }
+
+    fn select_failed(&self, platform: &str) -> Result<Vec<FailedEntry>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, url, reason, attempts, last_attempt FROM failed
+                WHERE platform = ? ORDER BY id",
+        )?;
+        statement.bind((1, platform))?;
+        let mut ret: Vec<FailedEntry> = Vec::new();
+        while let State::Row = statement.next()? {
+            ret.push(FailedEntry {
+                id: statement.read::<i64, _>(0)?,
+                url: statement.read::<String, _>(1)?,
+                reason: statement.read::<String, _>(2)?,
+                attempts: statement.read::<i64, _>(3)?,
+                last_attempt: statement.read::<i64, _>(4)?,
+            });
+        }
+        return Ok(ret);
+    }
+}
+
+/// adds a post to the `failed` table or counts another attempt
+fn record_failure(connection: &Connection, fail: Fail) -> Result<(), Error> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let mut statement = connection.prepare(
+        "INSERT INTO failed VALUES(?, ?, ?, ?, 1, ?)
+            ON CONFLICT(platform, id) DO UPDATE SET
+                url = excluded.url,
+                reason = excluded.reason,
+                attempts = attempts + 1,
+                last_attempt = excluded.last_attempt",
+    )?;
+    statement.bind_iter::<_, (usize, Value)>([
+        (1, Value::String(fail.platform.to_string())),
+        (2, Value::Integer(fail.id)),
+        (3, Value::String(fail.url)),
+        (4, Value::String(fail.reason)),
+        (5, Value::Integer(now)),
+    ])?;
+    while let State::Row = statement.next()? {}
+    return Ok(());
+}
+
+/// removes a post with its tags and index entry so the next run downloads it again
+fn remove_entry(connection: &Connection, platform: &str, id: i64) -> Result<(), Error> {
+    for query in [
+        format!("DELETE FROM {platform} WHERE id = ?"),
+        format!(
+            "DELETE FROM {} WHERE rowid = ?",
+            schema::fts_table(platform)
+        ),
+    ] {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, id))?;
+        while let State::Row = statement.next()? {}
+    }
+    for query in [
+        "DELETE FROM crossref WHERE id = ? AND platform = ?",
+        "DELETE FROM imported WHERE id = ? AND platform = ?",
+        "DELETE FROM post_tags
+            WHERE post = ? AND tag IN (SELECT id FROM tags WHERE platform = ?)",
+    ] {
+        let mut statement = connection.prepare(query)?;
+        statement.bind((1, id))?;
+        statement.bind((2, platform))?;
+        while let State::Row = statement.next()? {}
+    }
+    return Ok(());
+}
+
+/// records a saved post as failed so `retry-failed` downloads it again, the post is kept
+/// so its metadata isn't lost when the download doesn't work out
+pub(crate) fn requeue_entry(
+    connection: &Connection,
+    platform: &'static str,
+    entry: &DbEntry,
+    reason: String,
+) -> Result<(), Error> {
+    return record_failure(
+        connection,
+        Fail {
+            platform,
+            id: entry.id,
+            url: entry.file_url.clone().unwrap_or_default(),
+            reason,
+        },
+    );
+}
+
+/// removes a post in a transaction
+pub(crate) fn prune_entry(
+    connection: &Connection,
+    platform: &'static str,
+    entry: &DbEntry,
+) -> Result<(), Error> {
+    connection.execute("BEGIN")?;
+    match remove_entry(connection, platform, entry.id) {
+        Ok(_) => connection.execute("COMMIT")?,
+        Err(e) => {
+            connection.execute("ROLLBACK").ok();
+            return Err(e);
         }
     }
+    return Ok(());
 }
 
-#[allow(unused_must_use)]
-fn image_resize(mut request: ImageRequest) {
+/// columns of a platform table in the order of `DbEntry`
+pub(crate) const COLUMNS: &str =
+    "id, md5, source, tags, path, compress_path, rating, score, width, height, \
+    file_size, file_ext, created_at, updated_at, author, parent_id, status, file_url, dhash";
+
+/// reads a row of a platform table selected with `COLUMNS`
+pub(crate) fn read_entry(statement: &Statement) -> Result<DbEntry, Error> {
+    Ok(DbEntry {
+        id: statement.read::<i64, _>(0)?,
+        md5: statement.read::<String, _>(1)?,
+        source: statement.read::<Option<String>, _>(2)?,
+        tags: statement.read::<Option<String>, _>(3)?,
+        path: statement.read::<String, _>(4)?,
+        compress_path: statement.read::<Option<String>, _>(5)?,
+        rating: statement.read::<Option<String>, _>(6)?,
+        score: statement.read::<Option<i64>, _>(7)?,
+        width: statement.read::<Option<i64>, _>(8)?,
+        height: statement.read::<Option<i64>, _>(9)?,
+        file_size: statement.read::<Option<i64>, _>(10)?,
+        file_ext: statement.read::<Option<String>, _>(11)?,
+        created_at: statement.read::<Option<i64>, _>(12)?,
+        updated_at: statement.read::<Option<i64>, _>(13)?,
+        author: statement.read::<Option<String>, _>(14)?,
+        parent_id: statement.read::<Option<i64>, _>(15)?,
+        status: statement.read::<Option<String>, _>(16)?,
+        file_url: statement.read::<Option<String>, _>(17)?,
+        dhash: statement.read::<Option<i64>, _>(18)?,
+    })
+}
+
+fn image_resize(request: &mut ImageRequest) -> Result<PathBuf, Error> {
     use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageReader};
     use std::{fs, io};
+
+    // removes the previous output if there's any
+    let prepare = |dest_path: &Path| -> Result<(), io::Error> {
+        if let Some(parent) = dest_path.parent() {
+            utils::recursive_dir_create_blocking(parent)?;
+        }
+        match fs::metadata(dest_path) {
+            Ok(m) if m.st_size() > 0 => fs::remove_file(dest_path),
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    };
     let mut dest_path = request.dest.iter().collect::<PathBuf>();
-    match utils::recursive_dir_create_blocking(dest_path.parent().unwrap()) {
-        Ok(_) => match fs::metadata(&dest_path) {
-            Ok(m) => {
-                if m.st_size() > 0 {
-                    fs::remove_file(&dest_path).unwrap();
-                }
+    match prepare(&dest_path) {
+        Ok(_) => (),
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => match request.fallback.take() {
+            Some(fallback) => {
+                request.dest[0] = fallback;
+                dest_path = request.dest.iter().collect::<PathBuf>();
+                prepare(&dest_path).map_err(|e| Error::file(&dest_path, e))?;
             }
-            Err(_) => (),
-        },
-        Err(e) => match e.kind() {
-            ErrorKind::PermissionDenied => match request.fallback {
-                Some(fallback) => {
-                    request.dest[0] = fallback;
-                    dest_path = request.dest.iter().collect::<PathBuf>();
-                    match utils::recursive_dir_create_blocking(dest_path.parent().unwrap()) {
-                        Ok(_) => match fs::metadata(&dest_path) {
-                            Ok(m) => {
-                                if m.st_size() > 0 {
-                                    fs::remove_file(&dest_path).unwrap();
-                                }
-                            }
-                            Err(e) => match e.kind() {
-                                ErrorKind::NotFound => (),
-                                _ => panic!("{e:?}"),
-                            },
-                        },
-                        Err(e) => panic!("{e:?}"),
-                    }
-                }
-                None => {
-                    eprintln!(
-                        "could not create directory '{}', permission denied.",
-                        dest_path.display()
-                    );
-                    exit(e.raw_os_error().unwrap_or(1));
-                }
-            },
-            _ => panic!("{e:?}"),
+            None => return Err(Error::file(&dest_path, e)),
         },
+        Err(e) => return Err(Error::file(&dest_path, e)),
     }
-    let mut src_image: DynamicImage = match ImageReader::open(&request.src).unwrap().decode() {
-        Ok(d) => d,
-        Err(_) => {
-            eprintln!("decode error for {}", request.src.display());
-            request.response_channel.send(None);
-            return;
-        }
-    };
+    let mut src_image: DynamicImage = ImageReader::open(&request.src)
+        .map_err(|e| Error::file(&request.src, e))?
+        .with_guessed_format()
+        .map_err(|e| Error::file(&request.src, e))?
+        .decode()?;
     let src_size: (u32, u32) = (src_image.width(), src_image.height());
     if src_size.0 >= request.size.0 || src_size.1 >= request.size.0 {
         src_image = src_image.resize(request.size.0, request.size.0, FilterType::Lanczos3);
     }
-    let dest_file: fs::File = match fs::OpenOptions::new()
+    let dest_file: fs::File = fs::OpenOptions::new()
         .create(true)
         .write(true)
+        .truncate(true)
         .open(&dest_path)
-    {
-        Ok(f) => f,
-        Err(e) => {
-            eprintln!("error while opening file: {}", dest_path.display());
-            panic!("{e:?}");
-        }
-    };
+        .map_err(|e| Error::file(&dest_path, e))?;
     let writer: io::BufWriter<fs::File> = io::BufWriter::new(dest_file);
     let encoder: JpegEncoder<io::BufWriter<fs::File>> = JpegEncoder::new_with_quality(writer, 90);
-    match src_image.into_rgb8().write_with_encoder(encoder) {
-        Ok(_) => (),
-        Err(e) => panic!("{:?}", e),
-    };
-    request.response_channel.send(Some(dest_path));
-    return;
+    src_image.into_rgb8().write_with_encoder(encoder)?;
+    return Ok(dest_path);
 }